@@ -38,6 +38,8 @@ pub enum Error {
     ImageOpenError(PathBuf, std::io::Error),
     #[error("Failed to decode image {0} due {1}")]
     ImageDecodeError(PathBuf, Box<ImageError>),
+    #[error("Failed to load palette {0} due {1}")]
+    PaletteLoadError(PathBuf, image_blp::convert::Error),
 }
 
 /// Input images that we can decode
@@ -189,6 +191,23 @@ impl From<MipmapFilter> for FilterType {
     }
 }
 
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+pub enum MipmapColorSpaceCli {
+    /// Filter stored values directly
+    Srgb,
+    /// Filter in linear light, keeps brightness of high contrast textures
+    Linear,
+}
+
+impl From<MipmapColorSpaceCli> for MipmapColorSpace {
+    fn from(value: MipmapColorSpaceCli) -> MipmapColorSpace {
+        match value {
+            MipmapColorSpaceCli::Srgb => MipmapColorSpace::Srgb,
+            MipmapColorSpaceCli::Linear => MipmapColorSpace::Linear,
+        }
+    }
+}
+
 #[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
 pub enum DxtAlgorithmCli {
     /// Range fit, fast, poor quality
@@ -209,6 +228,76 @@ impl From<DxtAlgorithmCli> for DxtAlgorithm {
     }
 }
 
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+pub enum JpegSubsamplingCli {
+    /// Full color resolution
+    #[clap(name = "444")]
+    Ratio444,
+    /// Half horizontal color resolution
+    #[clap(name = "422")]
+    Ratio422,
+    /// Half horizontal and vertical color resolution
+    #[clap(name = "420")]
+    Ratio420,
+}
+
+impl From<JpegSubsamplingCli> for JpegSubsampling {
+    fn from(value: JpegSubsamplingCli) -> JpegSubsampling {
+        match value {
+            JpegSubsamplingCli::Ratio444 => JpegSubsampling::Ratio444,
+            JpegSubsamplingCli::Ratio422 => JpegSubsampling::Ratio422,
+            JpegSubsamplingCli::Ratio420 => JpegSubsampling::Ratio420,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+pub enum PaletteQuantizerCli {
+    /// Neural network quantizer, fast
+    Neuquant,
+    /// Median cut, keeps rare distinct colors
+    MedianCut,
+    /// Median cut refined by k-means clustering, slow, the best quality
+    KMeans,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+pub enum PaletteTrainingCli {
+    /// Build palette from the root image only
+    RootLevel,
+    /// Build palette from all mipmap levels
+    AllLevels,
+}
+
+impl From<PaletteTrainingCli> for PaletteTraining {
+    fn from(value: PaletteTrainingCli) -> PaletteTraining {
+        match value {
+            PaletteTrainingCli::RootLevel => PaletteTraining::RootLevel,
+            PaletteTrainingCli::AllLevels => PaletteTraining::AllLevels,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+pub enum DitheringCli {
+    /// Nearest color, gives banding on gradients
+    None,
+    /// Error diffusion
+    FloydSteinberg,
+    /// Bayer threshold matrix
+    Ordered,
+}
+
+impl From<DitheringCli> for Dithering {
+    fn from(value: DitheringCli) -> Dithering {
+        match value {
+            DitheringCli::None => Dithering::None,
+            DitheringCli::FloydSteinberg => Dithering::FloydSteinberg,
+            DitheringCli::Ordered => Dithering::Ordered,
+        }
+    }
+}
+
 /// Conversion of Warcraft III BLP format
 #[derive(Parser, Debug)]
 #[clap(author, version, about, long_about = None)]
@@ -260,15 +349,123 @@ struct Args {
     #[clap(long, value_parser, default_value = "lanczos3")]
     mipmap_filter: MipmapFilter,
 
+    /// Color space to scale mipmaps down in. Linear avoids darkening of
+    /// mipmaps of high contrast textures.
+    #[clap(long, value_parser, default_value = "srgb")]
+    mipmap_color_space: MipmapColorSpaceCli,
+
+    /// Whether to filter mipmaps with colors weighted by alpha, so colors of
+    /// transparent pixels don't leak into the visible ones.
+    #[clap(long)]
+    mipmap_premultiplied_alpha: bool,
+
+    /// Whether to fill colors of fully transparent pixels from the nearest
+    /// visible ones before mipmapping and compression. Removes dark or white
+    /// halos around transparent regions.
+    #[clap(long)]
+    alpha_bleed: bool,
+
+    /// Alpha test threshold to preserve alpha coverage of mipmaps at, e.g. 128
+    /// for 1 bit alpha. Keeps alpha tested textures like foliage from going bald
+    /// at distance.
+    #[clap(long)]
+    alpha_coverage_threshold: Option<u8>,
+
     /// Defines algorithm to use when compressing to BLP DXTn type.
     /// Fastest algorithm has poor quality. Medium is slow, but produces
     /// good results. And there also finest that is very slow, but with
     /// the greatest quality.
     #[clap(long, value_parser, default_value = "medium")]
     dxt_compression: DxtAlgorithmCli,
+
+    /// Quality of JPEG compression from 1 (smallest) to 100 (best).
+    #[clap(long, value_parser = clap::value_parser!(u8).range(1..=100), default_value = "75")]
+    jpeg_quality: u8,
+
+    /// Chroma subsampling of JPEG compression. 444 keeps full color resolution,
+    /// 420 halves it in both directions.
+    #[clap(long, value_parser, default_value = "420")]
+    jpeg_subsampling: JpegSubsamplingCli,
+
+    /// Whether to use progressive JPEG encoding instead of baseline one.
+    #[clap(long)]
+    jpeg_progressive: bool,
+
+    /// Algorithm that builds the palette when encoding to BLP Raw1 type.
+    #[clap(long, value_parser, default_value = "neuquant")]
+    palette_quantizer: PaletteQuantizerCli,
+
+    /// Speed-quality factor of neuquant palette quantizer from 1 (best quality)
+    /// to 30 (fastest).
+    #[clap(long, value_parser = clap::value_parser!(i32).range(1..=30), default_value = "10")]
+    neuquant_sample_factor: i32,
+
+    /// Number of refinement passes of k-means palette quantizer.
+    #[clap(long, default_value = "8")]
+    kmeans_iterations: u32,
+
+    /// Which mipmap levels contribute to the shared palette of BLP Raw1 type.
+    /// Training on all levels keeps colors of small mipmaps closer to the source.
+    #[clap(long, value_parser, default_value = "root-level")]
+    palette_training: PaletteTrainingCli,
+
+    /// Dithering of colors and 1 or 4 bits alpha when encoding to BLP Raw1 type.
+    #[clap(long, value_parser, default_value = "none")]
+    dithering: DitheringCli,
+
+    /// Fixed palette to use when encoding to BLP Raw1 type instead of building
+    /// a new one. Could be GIMP palette (.gpl), JASC or RIFF palette (.pal),
+    /// raw RGB table (.act) or image with at most 256 colors.
+    #[clap(long, value_parser)]
+    palette: Option<PathBuf>,
+}
+
+fn make_palette_options(args: &Args, padding: u8) -> Result<PaletteOptions, Error> {
+    let quantizer = match args.palette_quantizer {
+        PaletteQuantizerCli::Neuquant => PaletteQuantizer::NeuQuant {
+            sample_factor: args.neuquant_sample_factor,
+        },
+        PaletteQuantizerCli::MedianCut => PaletteQuantizer::MedianCut,
+        PaletteQuantizerCli::KMeans => PaletteQuantizer::KMeans {
+            iterations: args.kmeans_iterations,
+        },
+    };
+    let palette = match &args.palette {
+        Some(path) => {
+            Some(load_palette(path).map_err(|e| Error::PaletteLoadError(path.clone(), e))?)
+        }
+        None => None,
+    };
+    Ok(PaletteOptions {
+        quantizer,
+        training: Some(args.palette_training.into()),
+        dithering: args.dithering.into(),
+        palette,
+        padding,
+    })
+}
+
+fn make_mipmap_options(args: &Args) -> MipmapOptions {
+    MipmapOptions {
+        filter: args.mipmap_filter.into(),
+        color_space: args.mipmap_color_space.into(),
+        premultiplied_alpha: args.mipmap_premultiplied_alpha,
+        alpha_bleed: args.alpha_bleed,
+        coverage_threshold: args.alpha_coverage_threshold,
+    }
 }
 
-fn make_target_blp_format(args: &Args) -> Result<BlpTarget, Error> {
+fn make_jpeg_options(args: &Args) -> JpegOptions {
+    JpegOptions {
+        quality: args.jpeg_quality,
+        subsampling: args.jpeg_subsampling.into(),
+        progressive: args.jpeg_progressive,
+    }
+}
+
+/// `cmap_padding` is the padding byte of source color map to keep on BLP to
+/// BLP conversion.
+fn make_target_blp_format(args: &Args, cmap_padding: u8) -> Result<BlpTarget, Error> {
     match args.blp_version {
         OutputBlpVersion::Blp0 => match args.blp_format {
             OutputBlpFormat::Raw1 => {
@@ -285,7 +482,10 @@ fn make_target_blp_format(args: &Args) -> Result<BlpTarget, Error> {
                         ))
                     }
                 };
-                Ok(BlpTarget::Blp0(BlpOldFormat::Raw1 { alpha_bits }))
+                Ok(BlpTarget::Blp0(BlpOldFormat::Raw1 {
+                    alpha_bits,
+                    options: make_palette_options(args, cmap_padding)?,
+                }))
             }
             OutputBlpFormat::Jpeg => {
                 let has_alpha = match args.alpha_bits {
@@ -299,7 +499,10 @@ fn make_target_blp_format(args: &Args) -> Result<BlpTarget, Error> {
                         ))
                     }
                 };
-                Ok(BlpTarget::Blp0(BlpOldFormat::Jpeg { has_alpha }))
+                Ok(BlpTarget::Blp0(BlpOldFormat::Jpeg {
+                    has_alpha,
+                    options: make_jpeg_options(args),
+                }))
             }
             _ => Err(Error::BlpOldSupport(args.blp_version, args.blp_format)),
         },
@@ -318,7 +521,10 @@ fn make_target_blp_format(args: &Args) -> Result<BlpTarget, Error> {
                         ))
                     }
                 };
-                Ok(BlpTarget::Blp1(BlpOldFormat::Raw1 { alpha_bits }))
+                Ok(BlpTarget::Blp1(BlpOldFormat::Raw1 {
+                    alpha_bits,
+                    options: make_palette_options(args, cmap_padding)?,
+                }))
             }
             OutputBlpFormat::Jpeg => {
                 let has_alpha = match args.alpha_bits {
@@ -332,7 +538,10 @@ fn make_target_blp_format(args: &Args) -> Result<BlpTarget, Error> {
                         ))
                     }
                 };
-                Ok(BlpTarget::Blp1(BlpOldFormat::Jpeg { has_alpha }))
+                Ok(BlpTarget::Blp1(BlpOldFormat::Jpeg {
+                    has_alpha,
+                    options: make_jpeg_options(args),
+                }))
             }
             _ => Err(Error::BlpOldSupport(args.blp_version, args.blp_format)),
         },
@@ -351,7 +560,10 @@ fn make_target_blp_format(args: &Args) -> Result<BlpTarget, Error> {
                         ))
                     }
                 };
-                Ok(BlpTarget::Blp2(Blp2Format::Raw1 { alpha_bits }))
+                Ok(BlpTarget::Blp2(Blp2Format::Raw1 {
+                    alpha_bits,
+                    options: make_palette_options(args, cmap_padding)?,
+                }))
             }
             OutputBlpFormat::Raw3 => Ok(BlpTarget::Blp2(Blp2Format::Raw3)),
             OutputBlpFormat::Jpeg => {
@@ -366,7 +578,10 @@ fn make_target_blp_format(args: &Args) -> Result<BlpTarget, Error> {
                         ))
                     }
                 };
-                Ok(BlpTarget::Blp2(Blp2Format::Jpeg { has_alpha }))
+                Ok(BlpTarget::Blp2(Blp2Format::Jpeg {
+                    has_alpha,
+                    options: make_jpeg_options(args),
+                }))
             }
             OutputBlpFormat::Dxt1 => {
                 let has_alpha = match args.alpha_bits {
@@ -456,27 +671,33 @@ fn run_conv() -> Result<(), Error> {
     };
 
     trace!("Reading input image");
-    let input_image = if input_format == InputFormat::Blp {
+    let (input_image, cmap_padding) = if input_format == InputFormat::Blp {
         let blp_image = image_blp::parser::load_blp(&args.input_file)
             .map_err(|e| Error::LoadError(args.input_file.clone(), e))?;
+        let cmap_padding = match &blp_image.content {
+            BlpContent::Raw1(raw1) => image_blp::convert::cmap_padding(&raw1.cmap),
+            _ => 0,
+        };
 
-        image_blp::convert::blp_to_image(&blp_image, args.mipmap_level)
-            .map_err(|e| Error::Convert(args.input_file.clone(), e))?
+        let image = image_blp::convert::blp_to_image(&blp_image, args.mipmap_level)
+            .map_err(|e| Error::Convert(args.input_file.clone(), e))?;
+        (image, cmap_padding)
     } else {
-        ImageReader::open(&args.input_file)
+        let image = ImageReader::open(&args.input_file)
             .map_err(|e| Error::ImageOpenError(args.input_file.clone(), e))?
             .decode()
-            .map_err(|e| Error::ImageDecodeError(args.input_file.clone(), Box::new(e)))?
+            .map_err(|e| Error::ImageDecodeError(args.input_file.clone(), Box::new(e)))?;
+        (image, 0)
     };
 
     match output_format {
         OutputFormat::Blp => {
-            let target = make_target_blp_format(&args)?;
+            let target = make_target_blp_format(&args, cmap_padding)?;
             let new_blp = image_to_blp(
                 input_image,
                 !args.no_mipmaps,
                 target,
-                args.mipmap_filter.into(),
+                make_mipmap_options(&args),
             )
             .map_err(|e| Error::Convert(args.input_file.clone(), e))?;
             save_blp(&new_blp, &args.output_file).map_err(|e| {
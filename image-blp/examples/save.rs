@@ -16,6 +16,7 @@ fn main() {
         true,
         BlpTarget::Blp1(BlpOldFormat::Raw1 {
             alpha_bits: AlphaBits::Bit1,
+            options: Default::default(),
         }),
         FilterType::Nearest,
     )
@@ -0,0 +1,122 @@
+use std::fmt;
+
+/// Dithering that is applied when pixels are reduced to palette colors or
+/// to alpha of lower precision.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub enum Dithering {
+    /// Map each pixel to the nearest value. Gives banding on gradients.
+    #[default]
+    None,
+    /// Diffuse quantization error to the neighbour pixels
+    FloydSteinberg,
+    /// Offset pixels by 4x4 Bayer threshold matrix. Gives regular pattern
+    /// that is stable between mipmaps.
+    Ordered,
+}
+
+impl fmt::Display for Dithering {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Dithering::None => write!(f, "no dithering"),
+            Dithering::FloydSteinberg => write!(f, "Floyd-Steinberg dithering"),
+            Dithering::Ordered => write!(f, "ordered dithering"),
+        }
+    }
+}
+
+const BAYER_4X4: [[u8; 4]; 4] = [[0, 8, 2, 10], [12, 4, 14, 6], [3, 11, 1, 9], [15, 7, 13, 5]];
+
+/// Threshold of ordered dithering for the pixel in range -0.5..0.5
+fn bayer_offset(x: usize, y: usize) -> f32 {
+    (BAYER_4X4[y % 4][x % 4] as f32 + 0.5) / 16.0 - 0.5
+}
+
+/// Reduce image of `N` channel values in row order with the given dithering.
+///
+/// `quantize` maps the desired value to the output code and the value the
+/// code actually represents. `spread` is the distance between neighbour
+/// representable values that ordered dithering covers.
+pub fn dither<const N: usize, F>(
+    width: usize,
+    values: &[[f32; N]],
+    dithering: Dithering,
+    spread: f32,
+    mut quantize: F,
+) -> Vec<u8>
+where
+    F: FnMut([f32; N]) -> (u8, [f32; N]),
+{
+    let clamp = |value: [f32; N]| value.map(|v| v.clamp(0.0, 255.0));
+    match dithering {
+        Dithering::None => values.iter().map(|value| quantize(*value).0).collect(),
+        Dithering::Ordered => values
+            .iter()
+            .enumerate()
+            .map(|(i, value)| {
+                let offset = bayer_offset(i % width.max(1), i / width.max(1)) * spread;
+                quantize(clamp(value.map(|v| v + offset))).0
+            })
+            .collect(),
+        Dithering::FloydSteinberg => {
+            let width = width.max(1);
+            let mut current = vec![[0.0; N]; width + 2];
+            let mut next = vec![[0.0; N]; width + 2];
+            let mut result = Vec::with_capacity(values.len());
+            for (i, value) in values.iter().enumerate() {
+                let x = i % width;
+                if x == 0 && i > 0 {
+                    std::mem::swap(&mut current, &mut next);
+                    next.iter_mut().for_each(|error| *error = [0.0; N]);
+                }
+                // Errors are shifted by one to avoid bounds checks at the edges
+                let mut desired = *value;
+                for (d, e) in desired.iter_mut().zip(current[x + 1]) {
+                    *d += e;
+                }
+                let desired = clamp(desired);
+                let (code, actual) = quantize(desired);
+                let mut error = desired;
+                for (e, a) in error.iter_mut().zip(actual) {
+                    *e -= a;
+                }
+                add_error(&mut current[x + 2], &error, 7.0 / 16.0);
+                add_error(&mut next[x], &error, 3.0 / 16.0);
+                add_error(&mut next[x + 1], &error, 5.0 / 16.0);
+                add_error(&mut next[x + 2], &error, 1.0 / 16.0);
+                result.push(code);
+            }
+            result
+        }
+    }
+}
+
+fn add_error<const N: usize>(target: &mut [f32; N], error: &[f32; N], weight: f32) {
+    for (t, e) in target.iter_mut().zip(error) {
+        *t += e * weight;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_log::test;
+
+    fn one_bit(value: [f32; 1]) -> (u8, [f32; 1]) {
+        let code = (value[0] >= 128.0) as u8;
+        (code, [code as f32 * 255.0])
+    }
+
+    #[test]
+    fn test_dither_mean() {
+        // Flat gray becomes black and white pixels of the same mean brightness
+        let values = vec![[64.0]; 16 * 16];
+        assert!(dither(16, &values, Dithering::None, 255.0, one_bit)
+            .iter()
+            .all(|code| *code == 0));
+        for dithering in [Dithering::FloydSteinberg, Dithering::Ordered] {
+            let codes = dither(16, &values, dithering, 255.0, one_bit);
+            let ones = codes.iter().filter(|code| **code == 1).count();
+            assert!((60..=68).contains(&ones), "{} {}", dithering, ones);
+        }
+    }
+}
@@ -1,47 +1,35 @@
 use super::error::Error;
-use super::mipmap::generate_mipmaps;
 use crate::types::*;
-use ::image::{imageops::FilterType, DynamicImage, RgbaImage};
+use ::image::{DynamicImage, RgbaImage};
 
 pub fn dxtn_to_image(
     header: &BlpHeader,
-    image: &BlpDxtn,
+    format: DxtnFormat,
+    content: &[u8],
     mipmap_level: usize,
 ) -> Result<DynamicImage, Error> {
-    if mipmap_level >= image.images.len() {
-        return Err(Error::MissingImage(mipmap_level));
-    }
-    let raw_image = &image.images[mipmap_level];
     let (width, height) = header.mipmap_size(mipmap_level);
     let size = (width as usize) * (height as usize) * 4;
 
     let mut output = vec![0; size];
-    let decoder: texpresso::Format = image.format.into();
-    decoder.decompress(
-        &raw_image.content,
-        width as usize,
-        height as usize,
-        &mut output,
-    );
+    let decoder: texpresso::Format = format.into();
+    decoder.decompress(content, width as usize, height as usize, &mut output);
+    // Blocks can contain garbage alpha when the header declares no alpha
+    if header.alpha_bits() == 0 {
+        output.chunks_exact_mut(4).for_each(|pixel| pixel[3] = 255);
+    }
     let result = RgbaImage::from_raw(width, height, output).ok_or(Error::Dxt1RawConvertFail)?;
     Ok(DynamicImage::ImageRgba8(result))
 }
 
-pub fn image_to_dxtn(
-    image: DynamicImage,
+pub fn levels_to_dxtn(
+    levels: Vec<DynamicImage>,
     format: DxtnFormat,
-    make_mipmaps: bool,
-    mipmap_filter: FilterType,
     compress_algorithm: texpresso::Algorithm,
 ) -> Result<BlpDxtn, Error> {
-    let raw_images = if make_mipmaps {
-        generate_mipmaps(image, mipmap_filter)?.into_iter()
-    } else {
-        vec![image].into_iter()
-    };
     let encoder: texpresso::Format = format.into();
     let mut images = vec![];
-    for image in raw_images {
+    for image in levels {
         let rgba = image.into_rgba8();
         let width = rgba.width() as usize;
         let height = rgba.height() as usize;
@@ -61,3 +49,74 @@ pub fn image_to_dxtn(
         images,
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::super::{blp_to_image, Blp2Format, BlpTarget};
+    use super::*;
+    use crate::encode::encode_blp;
+    use crate::parser::parse_blp;
+    use crate::test_util::{image_blp, test_image};
+    use test_log::test;
+
+    fn test_blp(format: Blp2Format) -> BlpImage {
+        image_blp(test_image(8, 8, 100), false, BlpTarget::Blp2(format))
+    }
+
+    fn set_flags(blp: &mut BlpImage, new_alpha_bits: u8, new_alpha_type: u8) {
+        if let BlpFlags::Blp2 {
+            alpha_bits,
+            alpha_type,
+            ..
+        } = &mut blp.header.flags
+        {
+            *alpha_bits = new_alpha_bits;
+            *alpha_type = new_alpha_type;
+        }
+    }
+
+    #[test]
+    fn test_encoded_alpha_type() {
+        let formats = [
+            (
+                Blp2Format::Raw1 {
+                    alpha_bits: Default::default(),
+                    options: Default::default(),
+                },
+                8,
+            ),
+            (Blp2Format::Raw3, 2),
+            (Default::default(), 7),
+        ];
+        for (format, expected) in formats {
+            match test_blp(format).header.flags {
+                BlpFlags::Blp2 { alpha_type, .. } => assert_eq!(alpha_type, expected),
+                _ => panic!("expected BLP2 header"),
+            }
+        }
+    }
+
+    #[test]
+    fn test_alpha_type_8() {
+        let mut blp = test_blp(Default::default());
+        set_flags(&mut blp, 8, 8);
+        let bytes = encode_blp(&blp).expect("encoded");
+        let (_, parsed) = parse_blp(&bytes).expect("parsed");
+        assert!(matches!(parsed.content, BlpContent::Dxt5(_)));
+
+        set_flags(&mut blp, 3, 8);
+        let bytes = encode_blp(&blp).expect("encoded");
+        assert!(parse_blp(&bytes).is_err());
+    }
+
+    #[test]
+    fn test_no_alpha_is_opaque() {
+        let mut blp = test_blp(Default::default());
+        let decoded = blp_to_image(&blp, 0).expect("decoded").into_rgba8();
+        assert!(decoded.pixels().all(|p| p[3] < 255));
+
+        set_flags(&mut blp, 0, 7);
+        let decoded = blp_to_image(&blp, 0).expect("decoded").into_rgba8();
+        assert!(decoded.pixels().all(|p| p[3] == 255));
+    }
+}
@@ -19,12 +19,28 @@ pub enum Error {
         "Header sizes for mipmap {0} are {1}x{2}, but there are {3} alpha values actually in content."
     )]
     MismatchAlphaSizes(usize, u32, u32, usize),
+    #[error("Expected {0} mipmap levels for the root image size, but got {1}")]
+    MipmapCountMismatch(usize, usize),
+    #[error("Mipmap {0} should be {1}x{2}, but got {3}x{4}")]
+    MipmapSizeMismatch(usize, u32, u32, u32, u32),
     #[error("There are invalid alpha bits for the Raw1 format. Got {0}, expected: 0, 1, 4, 8.")]
     Raw1InvalidAlphaBits(u32),
     #[error("Color map length {0}, 256 expected!")]
     ColorMapLengthInvalid(usize),
-    #[error("Expected palette of 255 colors, but got {0}")]
+    #[error("Expected palette of at most 256 colors, but got {0}")]
     PaletteWrongSize(usize),
+    #[error("Failed to read palette file {0}, due: {1}")]
+    PaletteFileSystem(std::path::PathBuf, std::io::Error),
+    #[error("Invalid palette: {0}")]
+    PaletteInvalid(String),
+    #[error("JPEG quality should be in range 1-100, got {0}")]
+    JpegQuality(u8),
+    #[error("Failed to decode JPEG: {0}")]
+    JpegDecode(#[from] jpeg_decoder::Error),
+    #[error("Failed to encode JPEG: {0}")]
+    JpegEncode(#[from] jpeg_encoder::EncodingError),
+    #[error("{0} content cannot be stored in {1:?} without recompression")]
+    RepackUnsupported(&'static str, crate::types::BlpVersion),
     #[error("Failed to process bytes from DXT1 decomporession")]
     Dxt1RawConvertFail,
 }
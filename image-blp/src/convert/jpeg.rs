@@ -1,29 +1,86 @@
 use super::error::Error;
-use super::mipmap::generate_mipmaps;
+use super::{JpegOptions, JpegSubsampling};
 use crate::types::jpeg::MAX_JPEG_HEADER;
 use crate::types::*;
-use ::image::{
-    imageops::FilterType, io::Reader as ImageReader, DynamicImage, ImageFormat, RgbaImage,
-};
+use ::image::{DynamicImage, GrayImage, RgbImage, RgbaImage};
+use jpeg_decoder::{ColorTransform, Decoder, PixelFormat};
+use jpeg_encoder::{ColorType, Encoder, ImageBuffer, JpegColorType, SamplingFactor};
 use log::*;
-use std::io::Cursor;
-
-pub fn jpeg_to_image(image: &BlpJpeg, mipmap_level: usize) -> Result<DynamicImage, Error> {
-    let raw_jpeg = image
-        .full_jpeg(mipmap_level)
-        .ok_or(Error::MissingImage(mipmap_level))?;
-    let jpeg = ImageReader::with_format(Cursor::new(raw_jpeg), ImageFormat::Jpeg).decode()?;
-    let mut rgba = jpeg.into_rgba8();
+use std::io::{Cursor, Read};
+
+/// Decode JPEG of a mipmap level. Warcraft III writes 4 component JPEG with
+/// BGRA stored in components as is (without any color transform that Adobe
+/// marker implies), so such streams are decoded as CMYK and the inversion of
+/// components is undone to recover alpha plane exactly. 3 component streams
+/// contain BGR and are decoded as opaque images.
+pub fn jpeg_to_image(raw_jpeg: Vec<u8>) -> Result<DynamicImage, Error> {
+    decode_jpeg(Cursor::new(raw_jpeg))
+}
+
+/// Decode mipmap from shared header and its own body without copying them
+/// into one buffer
+pub fn jpeg_parts_to_image(header: &[u8], body: &[u8]) -> Result<DynamicImage, Error> {
+    // Remove those bugged 2 bytes from the end
+    let header = &header[..header.len().saturating_sub(2)];
+    decode_jpeg(header.chain(body))
+}
+
+fn decode_jpeg<R: Read>(reader: R) -> Result<DynamicImage, Error> {
+    let mut decoder = Decoder::new(reader);
+    decoder.read_info()?;
+    let info = decoder
+        .info()
+        .ok_or_else(|| jpeg_decoder::Error::Format("missing frame header".to_owned()))?;
+    let is_cmyk = matches!(info.pixel_format, PixelFormat::CMYK32);
+    if is_cmyk {
+        // Ignore YCCK transform of Adobe marker, components are stored as is
+        decoder.set_color_transform(ColorTransform::CMYK);
+    }
+    let mut pixels = decoder.decode()?;
+    if is_cmyk {
+        // CMYK conversion of the decoder inverts components
+        pixels.iter_mut().for_each(|value| *value = 255 - *value);
+    }
+    let width = info.width as u32;
+    let height = info.height as u32;
+    trace!(
+        "Decoded JPEG {}x{} with pixel format {:?}",
+        width,
+        height,
+        info.pixel_format
+    );
+    let rgba = match info.pixel_format {
+        PixelFormat::CMYK32 => RgbaImage::from_raw(width, height, pixels),
+        PixelFormat::RGB24 => RgbImage::from_raw(width, height, pixels)
+            .map(|image| DynamicImage::ImageRgb8(image).into_rgba8()),
+        PixelFormat::L8 => GrayImage::from_raw(width, height, pixels)
+            .map(|image| DynamicImage::ImageLuma8(image).into_rgba8()),
+        // Samples are big endian, keep the most significant byte
+        PixelFormat::L16 => GrayImage::from_raw(
+            width,
+            height,
+            pixels.chunks_exact(2).map(|sample| sample[0]).collect(),
+        )
+        .map(|image| DynamicImage::ImageLuma8(image).into_rgba8()),
+    };
+    let mut rgba = rgba.ok_or_else(|| {
+        jpeg_decoder::Error::Format("decoded pixels don't match frame sizes".to_owned())
+    })?;
     switch_red_blue(&mut rgba);
     Ok(DynamicImage::ImageRgba8(rgba))
 }
 
-pub fn image_to_jpeg(
-    image: &DynamicImage,
-    make_mipmaps: bool,
+/// Encode mipmap levels to JPEG with shared header. Levels should be
+/// already without alpha if `alpha_bits` is 0, so filtering of mipmaps
+/// doesn't depend on it.
+pub fn levels_to_jpeg(
+    levels: Vec<DynamicImage>,
     mut alpha_bits: u8,
-    mipmap_filter: FilterType,
+    options: &JpegOptions,
 ) -> Result<BlpJpeg, Error> {
+    if !(1..=100).contains(&options.quality) {
+        return Err(Error::JpegQuality(options.quality));
+    }
     if alpha_bits != 0 && alpha_bits != 8 {
         warn!(
             "Invalid alpha bits value for JPEG encoding {}, defaulting to 0",
@@ -31,34 +88,149 @@ pub fn image_to_jpeg(
         );
         alpha_bits = 0;
     }
-    let mut rgba = image.to_rgba8();
-    switch_red_blue(&mut rgba);
-    if alpha_bits == 0 {
-        fill_opaque_alpha(&mut rgba);
-    }
-
-    let mut images: Vec<Vec<u8>> = if make_mipmaps {
-        let images = generate_mipmaps(DynamicImage::ImageRgba8(rgba), mipmap_filter)?;
-        let jpeg_images: Result<Vec<Vec<u8>>, Error> = images
-            .into_iter()
-            .map(|image| {
-                let mut image_bytes = vec![];
-                image.write_to(&mut Cursor::new(&mut image_bytes), ImageFormat::Jpeg)?;
-                Ok(image_bytes)
-            })
-            .collect();
-        jpeg_images?
-    } else {
-        let mut root_img = vec![];
-        rgba.write_to(&mut Cursor::new(&mut root_img), ImageFormat::Jpeg)?;
-        vec![root_img]
-    };
-    let mut header = fetch_common_header(&mut images);
+    let mut images = vec![];
+    let mut tables_sizes = vec![];
+    for level in levels {
+        let mut rgba = level.into_rgba8();
+        switch_red_blue(&mut rgba);
+        if alpha_bits == 0 {
+            fill_opaque_alpha(&mut rgba);
+        }
+        let jpeg = encode_jpeg(&rgba, alpha_bits == 8, options)?;
+        let (jpeg, tables_size) = hoist_tables(&jpeg).unwrap_or((jpeg, 0));
+        images.push(jpeg);
+        tables_sizes.push(tables_size);
+    }
+    let mut header = fetch_shared_tables(&mut images, &tables_sizes).unwrap_or_else(|| {
+        debug!("JPEG tables differ between mipmaps, fallback to common prefix");
+        fetch_common_header(&mut images)
+    });
     // Add two padding bytes to the header as it always persists in War3 files
     header.extend(&vec![0; 2]);
     Ok(BlpJpeg { header, images })
 }
 
+/// Encode BGRA image. With alpha the image is written as 4 components
+/// without color transform the way Warcraft III expects, otherwise alpha is
+/// dropped and the usual YCbCr JPEG is produced.
+fn encode_jpeg(
+    image: &RgbaImage,
+    has_alpha: bool,
+    options: &JpegOptions,
+) -> Result<Vec<u8>, Error> {
+    let width = u16::try_from(image.width()).map_err(|_| Error::WidthTooLarge(image.width()))?;
+    let height =
+        u16::try_from(image.height()).map_err(|_| Error::HeightTooLarge(image.height()))?;
+    let mut output = vec![];
+    let mut encoder = Encoder::new(&mut output, options.quality);
+    // BGRA components are stored as is, subsampling would blur all of them but blue
+    encoder.set_sampling_factor(match options.subsampling {
+        _ if has_alpha => SamplingFactor::R_4_4_4,
+        JpegSubsampling::Ratio444 => SamplingFactor::R_4_4_4,
+        JpegSubsampling::Ratio422 => SamplingFactor::R_4_2_2,
+        JpegSubsampling::Ratio420 => SamplingFactor::R_4_2_0,
+    });
+    encoder.set_progressive(options.progressive);
+    // Optimized tables differ between images and couldn't be shared in the header
+    encoder.set_optimized_huffman_tables(false);
+    if has_alpha {
+        encoder.encode_image(BgraImage(image))?;
+    } else {
+        encoder.encode(image.as_raw(), width, height, ColorType::Rgba)?;
+    }
+    Ok(output)
+}
+
+/// Feeds BGRA pixels to the encoder as 4 components as is. The encoder
+/// marks them as CMYK, but BLP readers treat them as BGRA.
+struct BgraImage<'a>(&'a RgbaImage);
+
+impl<'a> ImageBuffer for BgraImage<'a> {
+    fn get_jpeg_color_type(&self) -> JpegColorType {
+        JpegColorType::Cmyk
+    }
+
+    fn width(&self) -> u16 {
+        self.0.width() as u16
+    }
+
+    fn height(&self) -> u16 {
+        self.0.height() as u16
+    }
+
+    fn fill_buffers(&self, y: u16, buffers: &mut [Vec<u8>; 4]) {
+        for x in 0..self.0.width() {
+            let pixel = self.0.get_pixel(x, y as u32);
+            for (buffer, value) in buffers.iter_mut().zip(pixel.0) {
+                buffer.push(value);
+            }
+        }
+    }
+}
+
+const MARKER_SOI: u8 = 0xD8;
+const MARKER_SOS: u8 = 0xDA;
+const MARKER_DQT: u8 = 0xDB;
+const MARKER_DHT: u8 = 0xC4;
+const MARKER_APP0: u8 = 0xE0;
+const MARKER_APP15: u8 = 0xEF;
+
+/// The encoder writes frame header (SOF) before quantization and Huffman
+/// tables. The frame header contains sizes of the image, so we move it after
+/// the tables to make all bytes before it the same for all mipmaps.
+///
+/// Returns reordered JPEG and size of its part up to the frame header.
+fn hoist_tables(jpeg: &[u8]) -> Option<(Vec<u8>, usize)> {
+    if jpeg.get(0..2)? != [0xFF, MARKER_SOI] {
+        return None;
+    }
+    let mut tables: Vec<&[u8]> = vec![];
+    let mut others: Vec<&[u8]> = vec![];
+    let mut pos = 2;
+    loop {
+        if *jpeg.get(pos)? != 0xFF {
+            return None;
+        }
+        let marker = *jpeg.get(pos + 1)?;
+        if marker == MARKER_SOS {
+            break;
+        }
+        let length = u16::from_be_bytes([*jpeg.get(pos + 2)?, *jpeg.get(pos + 3)?]) as usize;
+        let segment = jpeg.get(pos..pos + 2 + length)?;
+        match marker {
+            MARKER_DQT | MARKER_DHT | MARKER_APP0..=MARKER_APP15 => tables.push(segment),
+            _ => others.push(segment),
+        }
+        pos += 2 + length;
+    }
+
+    let mut result = Vec::with_capacity(jpeg.len());
+    result.extend([0xFF, MARKER_SOI]);
+    tables.iter().for_each(|segment| result.extend(*segment));
+    let tables_size = result.len();
+    others.iter().for_each(|segment| result.extend(*segment));
+    result.extend(&jpeg[pos..]);
+    Some((result, tables_size))
+}
+
+/// Take tables that are the same for all images as the JPEG header. Fails
+/// if the tables differ or they don't fit into [MAX_JPEG_HEADER].
+fn fetch_shared_tables(images: &mut [Vec<u8>], tables_sizes: &[usize]) -> Option<Vec<u8>> {
+    let size = *tables_sizes.first()?;
+    if size == 0 || size > MAX_JPEG_HEADER || tables_sizes.iter().any(|s| *s != size) {
+        return None;
+    }
+    let header = images[0][0..size].to_vec();
+    if images.iter().any(|image| image[0..size] != header[..]) {
+        return None;
+    }
+    trace!("Shared JPEG tables take {} bytes", size);
+    for image in images.iter_mut() {
+        image.drain(0..size);
+    }
+    Some(header)
+}
+
 fn switch_red_blue(image: &mut RgbaImage) {
     for pixel in image.pixels_mut() {
         let blue = pixel.0[0];
@@ -111,9 +283,172 @@ fn fetch_common_header(images: &mut [Vec<u8>]) -> Vec<u8> {
 
 #[cfg(test)]
 mod tests {
+    use super::super::mipmap::generate_mipmaps;
     use super::*;
+    use ::image::{imageops::FilterType, Rgba};
     use test_log::test;
 
+    fn mipmaps(image: RgbaImage) -> Vec<DynamicImage> {
+        generate_mipmaps(DynamicImage::ImageRgba8(image), &FilterType::Nearest.into())
+            .expect("mipmaps")
+    }
+
+    fn encode_test(options: &JpegOptions) -> Result<Vec<u8>, Error> {
+        let image = RgbaImage::from_fn(32, 32, |x, y| {
+            Rgba([(x * 8) as u8, (y * 8) as u8, ((x ^ y) * 8) as u8, 255])
+        });
+        let blp = levels_to_jpeg(mipmaps(image), 0, options)?;
+        let jpeg = blp.full_jpeg(0).expect("root image");
+        jpeg_to_image(jpeg.clone()).expect("decoded");
+        Ok(jpeg)
+    }
+
+    /// Find start of frame marker and return it with sampling factor of luma
+    fn frame_info(jpeg: &[u8]) -> (u8, u8) {
+        let pos = jpeg
+            .windows(2)
+            .position(|w| w[0] == 0xFF && (w[1] == 0xC0 || w[1] == 0xC2))
+            .expect("SOF marker");
+        // marker, length, precision, height, width, components count, id
+        (jpeg[pos + 1], jpeg[pos + 2 + 2 + 1 + 2 + 2 + 1 + 1])
+    }
+
+    #[test]
+    fn test_jpeg_options() {
+        let low = encode_test(&JpegOptions {
+            quality: 10,
+            ..Default::default()
+        })
+        .expect("encoded");
+        let high = encode_test(&JpegOptions {
+            quality: 100,
+            ..Default::default()
+        })
+        .expect("encoded");
+        assert!(low.len() < high.len());
+        assert_eq!(frame_info(&low), (0xC0, 0x22));
+
+        let options = JpegOptions {
+            subsampling: JpegSubsampling::Ratio422,
+            progressive: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            frame_info(&encode_test(&options).expect("encoded")),
+            (0xC2, 0x21)
+        );
+        let options = JpegOptions {
+            subsampling: JpegSubsampling::Ratio444,
+            ..Default::default()
+        };
+        assert_eq!(
+            frame_info(&encode_test(&options).expect("encoded")),
+            (0xC0, 0x11)
+        );
+
+        for quality in [0, 101] {
+            let options = JpegOptions {
+                quality,
+                ..Default::default()
+            };
+            assert!(matches!(encode_test(&options), Err(Error::JpegQuality(_))));
+        }
+    }
+
+    #[test]
+    fn test_shared_tables() {
+        for progressive in [false, true] {
+            let image = RgbaImage::from_fn(64, 32, |x, y| Rgba([x as u8, y as u8, 0, 255]));
+            let options = JpegOptions {
+                progressive,
+                ..Default::default()
+            };
+            let blp = levels_to_jpeg(mipmaps(image), 0, &options).expect("encoded");
+            let header = &blp.header[..blp.header.len() - 2];
+            assert!(header.len() <= MAX_JPEG_HEADER);
+            assert!(header.windows(2).any(|w| w == [0xFF, MARKER_DQT]));
+            assert!(header.windows(2).any(|w| w == [0xFF, MARKER_DHT]));
+            for (i, image) in blp.images.iter().enumerate() {
+                assert_eq!(image[0], 0xFF);
+                assert_eq!(image[1], if progressive { 0xC2 } else { 0xC0 });
+                let decoded = jpeg_to_image(blp.full_jpeg(i).expect("level")).expect("decoded");
+                assert_eq!(decoded.width(), (64 >> i).max(1));
+                assert_eq!(decoded.height(), (32 >> i).max(1));
+            }
+        }
+    }
+
+    #[test]
+    fn test_decode_bgra_components() {
+        // Sizes that are not multiples of MCU are padded inside the decoder
+        for (width, height) in [(16, 8), (13, 7)] {
+            let bgra = RgbaImage::from_fn(width, height, |x, y| {
+                Rgba([(x * 16) as u8, 128, (y * 32) as u8, (x * 16 + y) as u8])
+            });
+            let mut jpeg = vec![];
+            let encoder = Encoder::new(&mut jpeg, 100);
+            encoder.encode_image(BgraImage(&bgra)).expect("encoded");
+
+            let decoded = jpeg_to_image(jpeg).expect("decoded").into_rgba8();
+            assert_eq!(decoded.dimensions(), bgra.dimensions());
+            for (actual, expected) in decoded.pixels().zip(bgra.pixels()) {
+                let [b, g, r, a] = expected.0;
+                for (actual, expected) in actual.0.iter().zip([r, g, b, a]) {
+                    assert!(
+                        (*actual as i32 - expected as i32).abs() <= 4,
+                        "{:?} != {:?}",
+                        actual,
+                        expected
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_alpha_roundtrip() {
+        let image = RgbaImage::from_fn(32, 16, |x, y| {
+            Rgba([(x * 8) as u8, (y * 16) as u8, 64, (x * 8 + y) as u8])
+        });
+        let options = JpegOptions {
+            quality: 100,
+            ..Default::default()
+        };
+        let blp = levels_to_jpeg(mipmaps(image.clone()), 8, &options).expect("encoded");
+        let decoded = jpeg_to_image(blp.full_jpeg(0).expect("root image"))
+            .expect("decoded")
+            .into_rgba8();
+        for (actual, expected) in decoded.pixels().zip(image.pixels()) {
+            for (actual, expected) in actual.0.iter().zip(expected.0) {
+                assert!(
+                    (*actual as i32 - expected as i32).abs() <= 4,
+                    "{:?} != {:?}",
+                    actual,
+                    expected
+                );
+            }
+        }
+        for i in 1..blp.images.len() {
+            let level = jpeg_to_image(blp.full_jpeg(i).expect("level")).expect("decoded");
+            assert_eq!(level.width(), (32 >> i).max(1));
+        }
+
+        let opaque =
+            levels_to_jpeg(vec![DynamicImage::ImageRgba8(image)], 0, &options).expect("encoded");
+        let decoded = jpeg_to_image(opaque.full_jpeg(0).expect("root image")).expect("decoded");
+        assert!(decoded.into_rgba8().pixels().all(|p| p.0[3] == 255));
+    }
+
+    #[test]
+    fn test_hoist_tables_invalid() {
+        assert_eq!(hoist_tables(&[]), None);
+        assert_eq!(
+            hoist_tables(&[0xFF, MARKER_SOI, 0xFF, MARKER_DQT, 0, 10]),
+            None
+        );
+        assert_eq!(hoist_tables(&[0xFF, MARKER_SOI, 0x00]), None);
+    }
+
     #[test]
     fn test_fetch_common_header() {
         let mut images0 = vec![];
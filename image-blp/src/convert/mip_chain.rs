@@ -0,0 +1,174 @@
+use super::error::Error;
+use super::jpeg::jpeg_parts_to_image;
+use super::BlpSource;
+use crate::types::*;
+use ::image::DynamicImage;
+use rayon::prelude::*;
+
+/// All decoded mipmap levels of BLP image, see [blp_to_mip_chain]. Never
+/// empty, as [blp_to_mip_chain] fails for images without the root level.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MipChain {
+    levels: Vec<DynamicImage>,
+}
+
+impl MipChain {
+    /// Amount of levels including the root image
+    pub fn len(&self) -> usize {
+        self.levels.len()
+    }
+
+    /// Always false as decoded chain has at least the root image
+    pub fn is_empty(&self) -> bool {
+        false
+    }
+
+    /// The root (largest) image
+    pub fn root(&self) -> &DynamicImage {
+        &self.levels[0]
+    }
+
+    /// Image of the given mipmap level
+    pub fn get(&self, level: usize) -> Option<&DynamicImage> {
+        self.levels.get(level)
+    }
+
+    /// Width and height of the given mipmap level
+    pub fn dimensions(&self, level: usize) -> Option<(u32, u32)> {
+        self.get(level).map(|image| (image.width(), image.height()))
+    }
+
+    /// Iterate over levels from the root image to the smallest mipmap
+    pub fn iter(&self) -> std::slice::Iter<'_, DynamicImage> {
+        self.levels.iter()
+    }
+
+    /// Iterate over level index, width and height of each level
+    pub fn sizes(&self) -> impl Iterator<Item = (usize, u32, u32)> + '_ {
+        self.levels
+            .iter()
+            .enumerate()
+            .map(|(level, image)| (level, image.width(), image.height()))
+    }
+
+    /// The smallest level that is not smaller than given sizes in both
+    /// directions, e.g. to pick a thumbnail. Falls back to the root image.
+    pub fn level_at_least(&self, width: u32, height: u32) -> (usize, &DynamicImage) {
+        self.levels
+            .iter()
+            .enumerate()
+            .rev()
+            .find(|(_, image)| image.width() >= width && image.height() >= height)
+            .unwrap_or((0, self.root()))
+    }
+
+    /// Take decoded levels
+    pub fn into_levels(self) -> Vec<DynamicImage> {
+        self.levels
+    }
+}
+
+impl IntoIterator for MipChain {
+    type Item = DynamicImage;
+    type IntoIter = std::vec::IntoIter<DynamicImage>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.levels.into_iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a MipChain {
+    type Item = &'a DynamicImage;
+    type IntoIter = std::slice::Iter<'a, DynamicImage>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.levels.iter()
+    }
+}
+
+/// Decode all mipmap levels of the image in parallel. JPEG levels are
+/// decoded from the shared header and their bodies without joining them.
+pub fn blp_to_mip_chain(image: &BlpImage) -> Result<MipChain, Error> {
+    let count = image.image_count();
+    if count == 0 {
+        return Err(Error::MissingImage(0));
+    }
+    let levels = (0..count)
+        .into_par_iter()
+        .map(|level| match &image.content {
+            BlpContent::Jpeg(content) => {
+                jpeg_parts_to_image(&content.header, &content.images[level])
+            }
+            _ => image.mipmap_to_image(level),
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(MipChain { levels })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{
+        blp_to_image, image_to_blp, AlphaBits, Blp2Format, BlpOldFormat, BlpTarget,
+    };
+    use super::*;
+    use ::image::{imageops::FilterType, Rgba, RgbaImage};
+    use test_log::test;
+
+    #[test]
+    fn test_mip_chain() {
+        let image = RgbaImage::from_fn(32, 16, |x, y| {
+            Rgba([(x * 8) as u8, (y * 16) as u8, 64, 255])
+        });
+        let targets = [
+            BlpTarget::Blp1(BlpOldFormat::Jpeg {
+                has_alpha: true,
+                options: Default::default(),
+            }),
+            BlpTarget::Blp1(BlpOldFormat::Raw1 {
+                alpha_bits: AlphaBits::Bit8,
+                options: Default::default(),
+            }),
+            BlpTarget::Blp2(Blp2Format::Raw3),
+            BlpTarget::Blp2(Default::default()),
+        ];
+        for target in targets {
+            let blp = image_to_blp(
+                DynamicImage::ImageRgba8(image.clone()),
+                true,
+                target,
+                FilterType::Nearest,
+            )
+            .expect("converted");
+            let chain = blp_to_mip_chain(&blp).expect("decoded");
+            assert_eq!(chain.len(), blp.image_count());
+            assert!(!chain.is_empty());
+            for (level, width, height) in chain.sizes() {
+                assert_eq!((width, height), blp.header.mipmap_size(level));
+                assert_eq!(
+                    chain.get(level),
+                    Some(&blp_to_image(&blp, level).expect("converted"))
+                );
+            }
+            assert_eq!(chain.dimensions(0), Some((32, 16)));
+            assert_eq!(chain.level_at_least(5, 3).0, 2);
+            assert_eq!(chain.level_at_least(64, 64).0, 0);
+            assert_eq!(chain.iter().count(), chain.into_levels().len());
+        }
+
+        let mut empty = image_to_blp(
+            DynamicImage::ImageRgba8(image),
+            false,
+            BlpTarget::Blp2(Blp2Format::Raw3),
+            FilterType::Nearest,
+        )
+        .expect("converted");
+        match &mut empty.content {
+            BlpContent::Raw3(raw3) => raw3.images.clear(),
+            _ => panic!("expected RAW3 content"),
+        }
+        assert!(matches!(
+            blp_to_mip_chain(&empty),
+            Err(Error::MissingImage(0))
+        ));
+    }
+}
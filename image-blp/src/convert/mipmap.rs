@@ -1,22 +1,411 @@
 use super::error::Error;
-use ::image::{imageops::FilterType, DynamicImage};
+use super::MipmapOptions;
+use ::image::{imageops, DynamicImage, Rgba32FImage, RgbaImage};
+use std::fmt;
+
+/// Color space that mipmaps are filtered in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub enum MipmapColorSpace {
+    /// Filter stored sRGB values directly. Averages of bright and dark
+    /// pixels come out too dark, so high contrast textures dim in distance.
+    #[default]
+    Srgb,
+    /// Convert colors to linear light, filter and convert back to sRGB.
+    /// Alpha is filtered as is.
+    Linear,
+}
+
+impl fmt::Display for MipmapColorSpace {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MipmapColorSpace::Srgb => write!(f, "sRGB"),
+            MipmapColorSpace::Linear => write!(f, "linear light"),
+        }
+    }
+}
+
+/// Root image followed by its mipmaps if `make_mipmaps` is set. Applies
+/// [MipmapOptions::alpha_bleed] to the root image.
+pub fn mipmap_levels(
+    image: DynamicImage,
+    make_mipmaps: bool,
+    options: &MipmapOptions,
+) -> Result<Vec<DynamicImage>, Error> {
+    if make_mipmaps {
+        generate_mipmaps(image, options)
+    } else if options.alpha_bleed {
+        Ok(vec![bleed_alpha_dynamic(image)])
+    } else {
+        Ok(vec![image])
+    }
+}
 
 pub fn generate_mipmaps(
     image: DynamicImage,
-    filter: FilterType,
+    options: &MipmapOptions,
 ) -> Result<Vec<DynamicImage>, Error> {
+    // Mipmaps are filtered from the bled root, so they get bled colors too
+    let image = if options.alpha_bleed {
+        bleed_alpha_dynamic(image)
+    } else {
+        image
+    };
+    let sizes = mipmap_sizes(image.width(), image.height());
     let mut mipmaps = vec![image.clone()];
-    let mut current_image = image;
-    loop {
-        let width = current_image.width();
-        let height = current_image.height();
-        if (width == 1 && height == 1) || mipmaps.len() >= 16 {
-            break;
-        }
-        let new_width = width >> 1;
-        let new_height = height >> 1;
-        current_image = current_image.resize_exact(new_width, new_height, filter);
-        mipmaps.push(current_image.clone());
+    if options.color_space == MipmapColorSpace::Srgb && !options.premultiplied_alpha {
+        let mut current_image = image;
+        for (width, height) in sizes {
+            current_image = current_image.resize_exact(width, height, options.filter);
+            mipmaps.push(current_image.clone());
+        }
+    } else {
+        // Keep the chain in floats to not accumulate rounding errors
+        let mut current_image = to_filtered_space(image.into_rgba32f(), options);
+        for (width, height) in sizes {
+            current_image = imageops::resize(&current_image, width, height, options.filter);
+            mipmaps.push(DynamicImage::ImageRgba8(from_filtered_space(
+                &current_image,
+                options,
+            )));
+        }
+    }
+    if let Some(threshold) = options.coverage_threshold {
+        if mipmaps[0].color().has_alpha() {
+            let target = alpha_coverage(&mipmaps[0].to_rgba8(), threshold, 1.0);
+            for level in mipmaps.iter_mut().skip(1) {
+                let mut rgba = level.to_rgba8();
+                preserve_coverage(&mut rgba, threshold, target);
+                *level = DynamicImage::ImageRgba8(rgba);
+            }
+        }
     }
     Ok(mipmaps)
 }
+
+/// Fraction of pixels that pass alpha test at `threshold` after alpha is
+/// multiplied by `scale`
+fn alpha_coverage(image: &RgbaImage, threshold: u8, scale: f32) -> f32 {
+    let pixels = image.width() as usize * image.height() as usize;
+    let covered = image
+        .pixels()
+        .filter(|pixel| scale_alpha(pixel[3], scale) >= threshold)
+        .count();
+    covered as f32 / pixels.max(1) as f32
+}
+
+fn scale_alpha(alpha: u8, scale: f32) -> u8 {
+    (alpha as f32 * scale).round().min(255.0) as u8
+}
+
+/// Scale alpha of the mipmap so that the same fraction of pixels passes
+/// alpha test as in the root image. Coverage only grows with the scale, so
+/// the scale is found by bisection.
+fn preserve_coverage(level: &mut RgbaImage, threshold: u8, target: f32) {
+    let mut low = 0.0;
+    let mut high = 256.0;
+    let mut best_scale = 1.0;
+    let mut best_error = (alpha_coverage(level, threshold, 1.0) - target).abs();
+    for _ in 0..24 {
+        let scale = (low + high) / 2.0;
+        let coverage = alpha_coverage(level, threshold, scale);
+        let error = (coverage - target).abs();
+        if error < best_error {
+            best_scale = scale;
+            best_error = error;
+        }
+        if coverage < target {
+            low = scale;
+        } else {
+            high = scale;
+        }
+    }
+    for pixel in level.pixels_mut() {
+        pixel[3] = scale_alpha(pixel[3], best_scale);
+    }
+}
+
+/// Fill color of fully transparent pixels with average color of the nearest
+/// visible ones. Alpha is kept, so the image looks the same, but filtering
+/// and block compression don't pull dark or white halos from invisible
+/// pixels into the visible edges.
+pub fn bleed_alpha(image: &mut RgbaImage) {
+    let width = image.width() as usize;
+    let height = image.height() as usize;
+    let mut filled: Vec<bool> = image.pixels().map(|pixel| pixel[3] != 0).collect();
+    if filled.iter().all(|f| *f) || !filled.iter().any(|f| *f) {
+        return;
+    }
+    let neighbours = |i: usize| {
+        let (x, y) = ((i % width) as i64, (i / width) as i64);
+        (-1..=1)
+            .flat_map(move |dy| (-1..=1).map(move |dx| (x + dx, y + dy)))
+            .filter(move |&(nx, ny)| {
+                (nx, ny) != (x, y) && nx >= 0 && ny >= 0 && nx < width as i64 && ny < height as i64
+            })
+            .map(move |(nx, ny)| ny as usize * width + nx as usize)
+    };
+
+    // Spread colors layer by layer from the visible pixels
+    let mut queued = filled.clone();
+    let mut frontier = vec![];
+    for (i, &is_filled) in filled.iter().enumerate() {
+        if !is_filled && neighbours(i).any(|n| filled[n]) {
+            queued[i] = true;
+            frontier.push(i);
+        }
+    }
+    while !frontier.is_empty() {
+        let colors: Vec<[u8; 3]> = frontier
+            .iter()
+            .map(|&i| {
+                let mut sum = [0u32; 3];
+                let mut count = 0;
+                for n in neighbours(i).filter(|n| filled[*n]) {
+                    let pixel = image.get_pixel((n % width) as u32, (n / width) as u32);
+                    for (s, c) in sum.iter_mut().zip(pixel.0) {
+                        *s += c as u32;
+                    }
+                    count += 1;
+                }
+                sum.map(|s| ((s + count / 2) / count) as u8)
+            })
+            .collect();
+        for (&i, color) in frontier.iter().zip(colors) {
+            let pixel = image.get_pixel_mut((i % width) as u32, (i / width) as u32);
+            pixel.0[0..3].copy_from_slice(&color);
+            filled[i] = true;
+        }
+        let mut next = vec![];
+        for &i in frontier.iter() {
+            for n in neighbours(i) {
+                if !queued[n] {
+                    queued[n] = true;
+                    next.push(n);
+                }
+            }
+        }
+        frontier = next;
+    }
+}
+
+fn bleed_alpha_dynamic(image: DynamicImage) -> DynamicImage {
+    if !image.color().has_alpha() {
+        return image;
+    }
+    let mut rgba = image.into_rgba8();
+    bleed_alpha(&mut rgba);
+    DynamicImage::ImageRgba8(rgba)
+}
+
+/// Sizes of mipmaps after the root image. Same sizes as
+/// BlpHeader::mipmap_size gives for non square images: the short side stays
+/// at 1 pixel while the long one keeps halving, so a 64x8 image gets 6
+/// mipmaps down to 1x1 as BlpHeader::mipmaps_count expects.
+fn mipmap_sizes(mut width: u32, mut height: u32) -> Vec<(u32, u32)> {
+    let mut sizes = vec![];
+    while !(width == 1 && height == 1) && sizes.len() < 15 {
+        width = (width >> 1).max(1);
+        height = (height >> 1).max(1);
+        sizes.push((width, height));
+    }
+    sizes
+}
+
+fn to_filtered_space(mut image: Rgba32FImage, options: &MipmapOptions) -> Rgba32FImage {
+    for pixel in image.pixels_mut() {
+        let [r, g, b, a] = &mut pixel.0;
+        for value in [r, g, b] {
+            if options.color_space == MipmapColorSpace::Linear {
+                *value = if *value <= 0.04045 {
+                    *value / 12.92
+                } else {
+                    ((*value + 0.055) / 1.055).powf(2.4)
+                };
+            }
+            if options.premultiplied_alpha {
+                *value *= *a;
+            }
+        }
+    }
+    image
+}
+
+fn from_filtered_space(image: &Rgba32FImage, options: &MipmapOptions) -> RgbaImage {
+    let to_u8 = |value: f32| (value.clamp(0.0, 1.0) * 255.0).round() as u8;
+    RgbaImage::from_fn(image.width(), image.height(), |x, y| {
+        let [r, g, b, a] = image.get_pixel(x, y).0.map(|value| value.max(0.0));
+        let encode = |mut value: f32| {
+            if options.premultiplied_alpha {
+                value = if a > 0.0 { value / a } else { 0.0 };
+            }
+            if options.color_space == MipmapColorSpace::Linear {
+                value = if value <= 0.0031308 {
+                    value * 12.92
+                } else {
+                    1.055 * value.powf(1.0 / 2.4) - 0.055
+                };
+            }
+            to_u8(value)
+        };
+        ::image::Rgba([encode(r), encode(g), encode(b), to_u8(a)])
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::image::{imageops::FilterType, Rgba};
+    use test_log::test;
+
+    #[test]
+    fn test_non_square_mipmaps() {
+        let image = DynamicImage::ImageRgba8(RgbaImage::new(64, 8));
+        let sizes: Vec<(u32, u32)> = generate_mipmaps(image, &FilterType::Nearest.into())
+            .expect("mipmaps")
+            .iter()
+            .map(|level| (level.width(), level.height()))
+            .collect();
+        assert_eq!(
+            sizes,
+            [(64, 8), (32, 4), (16, 2), (8, 1), (4, 1), (2, 1), (1, 1)]
+        );
+    }
+
+    #[test]
+    fn test_linear_mipmaps() {
+        let checkers = RgbaImage::from_fn(2, 2, |x, y| {
+            if (x + y) % 2 == 0 {
+                Rgba([255, 255, 255, 255])
+            } else {
+                Rgba([0, 0, 0, 0])
+            }
+        });
+        let last_level = |color_space| {
+            let options = MipmapOptions {
+                filter: FilterType::Triangle,
+                color_space,
+                ..Default::default()
+            };
+            let mipmaps = generate_mipmaps(DynamicImage::ImageRgba8(checkers.clone()), &options)
+                .expect("mipmaps");
+            assert_eq!(mipmaps.len(), 2);
+            mipmaps[1].to_rgba8().get_pixel(0, 0).0
+        };
+        // Half of the light gives 188 in sRGB, while alpha stays linear
+        let [r, g, b, a] = last_level(MipmapColorSpace::Linear);
+        assert!((185..=191).contains(&r), "{}", r);
+        assert_eq!((r, g, b), (g, b, r));
+        assert!((125..=130).contains(&a), "{}", a);
+        let [r, _, _, _] = last_level(MipmapColorSpace::Srgb);
+        assert!((125..=130).contains(&r), "{}", r);
+    }
+
+    #[test]
+    fn test_premultiplied_mipmaps() {
+        // One visible red pixel among transparent white ones
+        let image = RgbaImage::from_fn(2, 2, |x, y| {
+            if x == 0 && y == 0 {
+                Rgba([255, 0, 0, 255])
+            } else {
+                Rgba([255, 255, 255, 0])
+            }
+        });
+        let last_level = |premultiplied_alpha| {
+            let options = MipmapOptions {
+                filter: FilterType::Triangle,
+                premultiplied_alpha,
+                ..Default::default()
+            };
+            let mipmaps = generate_mipmaps(DynamicImage::ImageRgba8(image.clone()), &options)
+                .expect("mipmaps");
+            mipmaps[1].to_rgba8().get_pixel(0, 0).0
+        };
+        let [r, g, b, a] = last_level(true);
+        assert_eq!((r, g, b), (255, 0, 0));
+        assert!((62..=66).contains(&a), "{}", a);
+        let [_, g, _, _] = last_level(false);
+        assert!(g > 150, "{}", g);
+    }
+
+    #[test]
+    fn test_alpha_bleed() {
+        let mut image = RgbaImage::from_fn(8, 4, |x, y| match (x, y) {
+            (0, 0) => Rgba([0, 200, 0, 255]),
+            (0, _) => Rgba([0, 200, 0, 128]),
+            _ => Rgba([255, 255, 255, 0]),
+        });
+        bleed_alpha(&mut image);
+        assert!(image.pixels().all(|pixel| pixel.0[0..3] == [0, 200, 0]));
+        assert_eq!(image.get_pixel(7, 3).0[3], 0);
+
+        // Neighbours are averaged
+        let mut line = RgbaImage::from_fn(3, 1, |x, _| match x {
+            0 => Rgba([0, 200, 0, 255]),
+            1 => Rgba([255, 255, 255, 0]),
+            _ => Rgba([0, 0, 200, 255]),
+        });
+        bleed_alpha(&mut line);
+        assert_eq!(line.get_pixel(1, 0).0, [0, 100, 100, 0]);
+
+        // Nothing to bleed from
+        let mut empty = RgbaImage::from_pixel(4, 4, Rgba([255, 255, 255, 0]));
+        bleed_alpha(&mut empty);
+        assert!(empty.pixels().all(|pixel| pixel.0 == [255, 255, 255, 0]));
+    }
+
+    #[test]
+    fn test_alpha_bleed_levels() {
+        let image = RgbaImage::from_fn(8, 8, |x, y| match (x, y) {
+            (0..=1, _) => Rgba([0, 200, 0, 255]),
+            (2..=3, _) => Rgba([200, 0, 0, 64]),
+            _ => Rgba([255, 255, 255, 0]),
+        });
+        let options = MipmapOptions {
+            alpha_bleed: true,
+            ..FilterType::Triangle.into()
+        };
+        let mut bled = image.clone();
+        bleed_alpha(&mut bled);
+        let root = mipmap_levels(DynamicImage::ImageRgba8(image.clone()), false, &options)
+            .expect("levels");
+        assert_eq!(root, vec![DynamicImage::ImageRgba8(bled)]);
+        // Mipmaps are filtered from the bled root and are not bled again
+        let levels =
+            mipmap_levels(DynamicImage::ImageRgba8(image.clone()), true, &options).expect("levels");
+        let mipmaps = generate_mipmaps(DynamicImage::ImageRgba8(image), &options).expect("mipmaps");
+        assert_eq!(levels, mipmaps);
+    }
+
+    #[test]
+    fn test_coverage_preserving() {
+        // Noisy alpha that averages out to the middle values in mipmaps
+        let image = RgbaImage::from_fn(64, 64, |x, y| {
+            Rgba([0, 0, 0, (((x * 73 + y * 151) ^ (x * y)) % 256) as u8])
+        });
+        let threshold = 200;
+        let target = alpha_coverage(&image, threshold, 1.0);
+        let coverages = |coverage_threshold| {
+            let options = MipmapOptions {
+                filter: FilterType::Triangle,
+                coverage_threshold,
+                ..Default::default()
+            };
+            generate_mipmaps(DynamicImage::ImageRgba8(image.clone()), &options)
+                .expect("mipmaps")
+                .into_iter()
+                .skip(1)
+                .take(3)
+                .map(|level| alpha_coverage(&level.to_rgba8(), threshold, 1.0))
+                .collect::<Vec<_>>()
+        };
+        for (plain, preserved) in coverages(None).into_iter().zip(coverages(Some(threshold))) {
+            assert!(
+                (preserved - target).abs() < 0.05,
+                "{} {}",
+                preserved,
+                target
+            );
+            assert!(plain < preserved, "{} {}", plain, preserved);
+        }
+    }
+}
@@ -1,31 +1,189 @@
+mod dither;
 mod dxtn;
 pub mod error;
 mod jpeg;
+mod mip_chain;
 mod mipmap;
 mod palette;
+mod palette_file;
 mod raw1;
 mod raw3;
+mod repack;
 
+use crate::parser::{BlpMipmap, BlpMipmapContent};
 use crate::types::*;
 pub use ::image::imageops::FilterType;
-use ::image::DynamicImage;
+use ::image::{DynamicImage, GrayImage};
+pub use dither::Dithering;
 use dxtn::*;
 pub use error::Error;
 use jpeg::*;
+pub use mip_chain::{blp_to_mip_chain, MipChain};
+use mipmap::mipmap_levels;
+pub use mipmap::{bleed_alpha, MipmapColorSpace};
+pub use palette::{
+    cmap_padding, KMeansQuantizer, MedianCutQuantizer, NeuQuantQuantizer, PaletteQuantizer,
+    PaletteTraining, Quantizer,
+};
+pub use palette_file::{load_palette, palette_from_image, parse_palette};
 use raw1::*;
 use raw3::*;
+pub use repack::repack_blp;
 use std::fmt;
 pub use texpresso::Algorithm as DxtAlgorithm;
 
-/// Convert from parsed raw BLP image to useful [DynamicImage]
-pub fn blp_to_image(image: &BlpImage, mipmap_level: usize) -> Result<DynamicImage, Error> {
-    match &image.content {
-        BlpContent::Raw1(content) => raw1_to_image(&image.header, content, mipmap_level),
-        BlpContent::Raw3(content) => raw3_to_image(&image.header, content, mipmap_level),
-        BlpContent::Jpeg(content) => jpeg_to_image(content, mipmap_level),
-        BlpContent::Dxt1(content) => dxtn_to_image(&image.header, content, mipmap_level),
-        BlpContent::Dxt3(content) => dxtn_to_image(&image.header, content, mipmap_level),
-        BlpContent::Dxt5(content) => dxtn_to_image(&image.header, content, mipmap_level),
+/// Convert from parsed raw BLP image to useful [DynamicImage]. Accepts both
+/// owned [BlpImage] and borrowed [BlpImageRef].
+pub fn blp_to_image<T: BlpSource + ?Sized>(
+    image: &T,
+    mipmap_level: usize,
+) -> Result<DynamicImage, Error> {
+    image.mipmap_to_image(mipmap_level)
+}
+
+/// Get alpha channel of paletted (RAW1) image at the precision it is stored
+/// in the file, e.g. 0..=15 for 4 bits alpha. Returns [None] for other
+/// formats or when the image has no alpha.
+pub fn blp_to_native_alpha<T: BlpSource + ?Sized>(
+    image: &T,
+    mipmap_level: usize,
+) -> Result<Option<GrayImage>, Error> {
+    image.mipmap_native_alpha(mipmap_level)
+}
+
+/// Parsed BLP image that can be converted with [blp_to_image]
+pub trait BlpSource {
+    /// Convert given mipmap level to [DynamicImage]
+    fn mipmap_to_image(&self, mipmap_level: usize) -> Result<DynamicImage, Error>;
+
+    /// Get alpha of given mipmap level without scaling, see [blp_to_native_alpha]
+    fn mipmap_native_alpha(&self, _mipmap_level: usize) -> Result<Option<GrayImage>, Error> {
+        Ok(None)
+    }
+}
+
+impl BlpSource for BlpImage {
+    fn mipmap_to_image(&self, mipmap_level: usize) -> Result<DynamicImage, Error> {
+        let header = &self.header;
+        let missing = || Error::MissingImage(mipmap_level);
+        match &self.content {
+            BlpContent::Raw1(content) => {
+                let image = content.images.get(mipmap_level).ok_or_else(missing)?;
+                let image = Raw1ImageRef {
+                    indexed_rgb: &image.indexed_rgb,
+                    indexed_alpha: &image.indexed_alpha,
+                };
+                raw1_to_image(header, &content.cmap, image, mipmap_level)
+            }
+            BlpContent::Raw3(content) => {
+                let image = content.images.get(mipmap_level).ok_or_else(missing)?;
+                raw3_to_image(header, image.pixels.iter().copied(), mipmap_level)
+            }
+            BlpContent::Jpeg(content) => {
+                jpeg_to_image(content.full_jpeg(mipmap_level).ok_or_else(missing)?)
+            }
+            BlpContent::Dxt1(content) | BlpContent::Dxt3(content) | BlpContent::Dxt5(content) => {
+                let image = content.images.get(mipmap_level).ok_or_else(missing)?;
+                dxtn_to_image(header, content.format, &image.content, mipmap_level)
+            }
+        }
+    }
+
+    fn mipmap_native_alpha(&self, mipmap_level: usize) -> Result<Option<GrayImage>, Error> {
+        match &self.content {
+            BlpContent::Raw1(content) => {
+                let image = content
+                    .images
+                    .get(mipmap_level)
+                    .ok_or(Error::MissingImage(mipmap_level))?;
+                let image = Raw1ImageRef {
+                    indexed_rgb: &image.indexed_rgb,
+                    indexed_alpha: &image.indexed_alpha,
+                };
+                raw1_alpha_to_image(&self.header, image, mipmap_level)
+            }
+            _ => Ok(None),
+        }
+    }
+}
+
+impl<'a> BlpSource for BlpImageRef<'a> {
+    fn mipmap_to_image(&self, mipmap_level: usize) -> Result<DynamicImage, Error> {
+        let header = &self.header;
+        let missing = || Error::MissingImage(mipmap_level);
+        match &self.content {
+            BlpContentRef::Raw1(content) => {
+                let image = content.images.get(mipmap_level).ok_or_else(missing)?;
+                raw1_to_image(header, &content.cmap, *image, mipmap_level)
+            }
+            BlpContentRef::Raw3(content) => {
+                let image = content.images.get(mipmap_level).ok_or_else(missing)?;
+                raw3_to_image(header, image.pixels(), mipmap_level)
+            }
+            BlpContentRef::Jpeg(content) => {
+                jpeg_to_image(content.full_jpeg(mipmap_level).ok_or_else(missing)?)
+            }
+            BlpContentRef::Dxt1(content)
+            | BlpContentRef::Dxt3(content)
+            | BlpContentRef::Dxt5(content) => {
+                let image = content.images.get(mipmap_level).ok_or_else(missing)?;
+                dxtn_to_image(header, content.format, image.content, mipmap_level)
+            }
+        }
+    }
+
+    fn mipmap_native_alpha(&self, mipmap_level: usize) -> Result<Option<GrayImage>, Error> {
+        match &self.content {
+            BlpContentRef::Raw1(content) => {
+                let image = content
+                    .images
+                    .get(mipmap_level)
+                    .ok_or(Error::MissingImage(mipmap_level))?;
+                raw1_alpha_to_image(&self.header, *image, mipmap_level)
+            }
+            _ => Ok(None),
+        }
+    }
+}
+
+impl<'a> BlpSource for BlpMipmap<'a> {
+    fn mipmap_to_image(&self, mipmap_level: usize) -> Result<DynamicImage, Error> {
+        if mipmap_level != self.level {
+            return Err(Error::MissingImage(mipmap_level));
+        }
+        let header = self.header;
+        match &self.content {
+            BlpMipmapContent::Jpeg { header, image } => jpeg_parts_to_image(header, image),
+            BlpMipmapContent::Raw1 { cmap, image } => {
+                let image = Raw1ImageRef {
+                    indexed_rgb: &image.indexed_rgb,
+                    indexed_alpha: &image.indexed_alpha,
+                };
+                raw1_to_image(header, cmap, image, mipmap_level)
+            }
+            BlpMipmapContent::Raw3(image) => {
+                raw3_to_image(header, image.pixels.iter().copied(), mipmap_level)
+            }
+            BlpMipmapContent::Dxtn { format, image } => {
+                dxtn_to_image(header, *format, &image.content, mipmap_level)
+            }
+        }
+    }
+
+    fn mipmap_native_alpha(&self, mipmap_level: usize) -> Result<Option<GrayImage>, Error> {
+        if mipmap_level != self.level {
+            return Err(Error::MissingImage(mipmap_level));
+        }
+        match &self.content {
+            BlpMipmapContent::Raw1 { image, .. } => {
+                let image = Raw1ImageRef {
+                    indexed_rgb: &image.indexed_rgb,
+                    indexed_alpha: &image.indexed_alpha,
+                };
+                raw1_alpha_to_image(self.header, image, mipmap_level)
+            }
+            _ => Ok(None),
+        }
     }
 }
 
@@ -50,6 +208,24 @@ impl Default for BlpTarget {
     }
 }
 
+impl BlpTarget {
+    fn is_opaque_jpeg(&self) -> bool {
+        matches!(
+            self,
+            BlpTarget::Blp0(BlpOldFormat::Jpeg {
+                has_alpha: false,
+                ..
+            }) | BlpTarget::Blp1(BlpOldFormat::Jpeg {
+                has_alpha: false,
+                ..
+            }) | BlpTarget::Blp2(Blp2Format::Jpeg {
+                has_alpha: false,
+                ..
+            })
+        )
+    }
+}
+
 impl fmt::Display for BlpTarget {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
@@ -64,32 +240,185 @@ impl fmt::Display for BlpTarget {
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum BlpOldFormat {
     /// Paletted 256 colors image with/without alpha.  
-    Raw1 { alpha_bits: AlphaBits },
+    Raw1 {
+        alpha_bits: AlphaBits,
+        /// Settings of palette conversion
+        options: PaletteOptions,
+    },
     /// JPEG encoding with/without alpha.
-    Jpeg { has_alpha: bool },
+    Jpeg {
+        has_alpha: bool,
+        /// Quality and compression settings of JPEG encoder
+        options: JpegOptions,
+    },
 }
 
 impl Default for BlpOldFormat {
     fn default() -> Self {
-        BlpOldFormat::Jpeg { has_alpha: true }
+        BlpOldFormat::Jpeg {
+            has_alpha: true,
+            options: Default::default(),
+        }
     }
 }
 
 impl fmt::Display for BlpOldFormat {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            BlpOldFormat::Raw1 { alpha_bits } => write!(f, "Palleted image with {}", alpha_bits),
-            BlpOldFormat::Jpeg { has_alpha } => {
+            BlpOldFormat::Raw1 {
+                alpha_bits,
+                options,
+            } => write!(f, "Palleted image with {}, {}", alpha_bits, options),
+            BlpOldFormat::Jpeg { has_alpha, options } => {
                 if *has_alpha {
-                    write!(f, "Jpeg image with alpha")
+                    write!(f, "Jpeg image with alpha, {}", options)
                 } else {
-                    write!(f, "Jpeg image without alpha")
+                    write!(f, "Jpeg image without alpha, {}", options)
                 }
             }
         }
     }
 }
 
+/// Settings of JPEG encoder for JPEG content
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct JpegOptions {
+    /// Quality from 1 (smallest size) to 100 (best quality)
+    pub quality: u8,
+    /// Resolution of color channels relative to brightness. Ignored for
+    /// images with alpha as all 4 components are stored in full resolution.
+    pub subsampling: JpegSubsampling,
+    /// Encode image in several passes of increasing detail instead of
+    /// a single baseline pass. Usually gives smaller files.
+    pub progressive: bool,
+}
+
+impl Default for JpegOptions {
+    fn default() -> Self {
+        JpegOptions {
+            quality: 75,
+            subsampling: Default::default(),
+            progressive: false,
+        }
+    }
+}
+
+impl fmt::Display for JpegOptions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mode = if self.progressive {
+            "progressive"
+        } else {
+            "baseline"
+        };
+        write!(
+            f,
+            "quality {}, subsampling {}, {}",
+            self.quality, self.subsampling, mode
+        )
+    }
+}
+
+/// Settings of palette conversion for paletted (RAW1) content
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct PaletteOptions {
+    /// Algorithm that builds the palette
+    pub quantizer: PaletteQuantizer,
+    /// Which mipmap levels contribute to the shared palette. By default only
+    /// the root level for generated mipmaps and all levels for hand made
+    /// chains of [mip_chain_to_blp], as their colors may differ from the root.
+    pub training: Option<PaletteTraining>,
+    /// Dithering of color indices and of 1 or 4 bits alpha
+    pub dithering: Dithering,
+    /// Fixed color map of 256 entries in layout of [BlpRaw1::cmap] to map
+    /// pixels onto, e.g. to share one palette between several textures. The
+    /// quantizer is not used then. See [load_palette].
+    pub palette: Option<Vec<u32>>,
+    /// High byte of each entry of the built color map. The game ignores it,
+    /// but some tools keep data there. Take it from the source color map with
+    /// [cmap_padding] to keep it on round trip. Not used with fixed palette,
+    /// which is written as is.
+    pub padding: u8,
+}
+
+impl fmt::Display for PaletteOptions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.training {
+            _ if self.palette.is_some() => write!(f, "fixed palette, {}", self.dithering),
+            Some(training) => write!(
+                f,
+                "quantizer {} {}, {}",
+                self.quantizer, training, self.dithering
+            ),
+            None => write!(f, "quantizer {}, {}", self.quantizer, self.dithering),
+        }
+    }
+}
+
+/// Settings of mipmap generation, shared by all [BlpTarget]s. Can be made
+/// from a [FilterType] alone.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MipmapOptions {
+    /// Which algorithm to use to scale mipmaps down
+    pub filter: FilterType,
+    /// Color space that filtering is done in
+    pub color_space: MipmapColorSpace,
+    /// Filter colors weighted by alpha, so colors of transparent pixels
+    /// don't bleed into the visible ones of mipmaps
+    pub premultiplied_alpha: bool,
+    /// Fill colors of fully transparent pixels from the nearest visible ones
+    /// before filtering and compression. Avoids halos around transparent
+    /// regions, especially with DXTn compression. See [bleed_alpha].
+    pub alpha_bleed: bool,
+    /// Alpha test threshold to preserve coverage at. Alpha of each mipmap is
+    /// rescaled so the same fraction of pixels passes the test as in the root
+    /// image, which keeps alpha tested foliage from thinning out at distance.
+    /// Use 128 for 1 bit alpha of RAW1 and DXT1.
+    pub coverage_threshold: Option<u8>,
+}
+
+impl Default for MipmapOptions {
+    fn default() -> Self {
+        MipmapOptions {
+            filter: FilterType::Lanczos3,
+            color_space: Default::default(),
+            premultiplied_alpha: false,
+            alpha_bleed: false,
+            coverage_threshold: None,
+        }
+    }
+}
+
+impl From<FilterType> for MipmapOptions {
+    fn from(filter: FilterType) -> Self {
+        MipmapOptions {
+            filter,
+            ..Default::default()
+        }
+    }
+}
+
+/// Chroma subsampling of JPEG encoder
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub enum JpegSubsampling {
+    /// Full resolution of color
+    Ratio444,
+    /// Half horizontal resolution of color
+    Ratio422,
+    /// Half horizontal and vertical resolution of color
+    #[default]
+    Ratio420,
+}
+
+impl fmt::Display for JpegSubsampling {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            JpegSubsampling::Ratio444 => write!(f, "4:4:4"),
+            JpegSubsampling::Ratio422 => write!(f, "4:2:2"),
+            JpegSubsampling::Ratio420 => write!(f, "4:2:0"),
+        }
+    }
+}
+
 /// Allowed alpha bits values for Raw1 encoding
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
 pub enum AlphaBits {
@@ -141,11 +470,19 @@ impl From<AlphaBits> for u8 {
 #[derive(Clone, PartialEq, Eq)]
 pub enum Blp2Format {
     /// Paletted 256 colors image with/without alpha.  
-    Raw1 { alpha_bits: AlphaBits },
+    Raw1 {
+        alpha_bits: AlphaBits,
+        /// Settings of palette conversion
+        options: PaletteOptions,
+    },
     /// RGBA bitmap
     Raw3,
     /// JPEG encoded image. Although, it is never used in real files.
-    Jpeg { has_alpha: bool },
+    Jpeg {
+        has_alpha: bool,
+        /// Quality and compression settings of JPEG encoder
+        options: JpegOptions,
+    },
     /// ST3C compression, type with 1 bit alpha or 0 bit alpha.
     Dxt1 {
         has_alpha: bool,
@@ -175,16 +512,33 @@ impl Default for Blp2Format {
     }
 }
 
+impl Blp2Format {
+    /// Value of `alpha_type` header field that Blizzard files use for the format
+    pub fn alpha_type(&self) -> u8 {
+        match self {
+            Blp2Format::Raw1 { .. } => 8,
+            Blp2Format::Raw3 => 2,
+            Blp2Format::Jpeg { .. } => 0,
+            Blp2Format::Dxt1 { .. } => 0,
+            Blp2Format::Dxt3 { .. } => 1,
+            Blp2Format::Dxt5 { .. } => 7,
+        }
+    }
+}
+
 impl fmt::Display for Blp2Format {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            Blp2Format::Raw1 { alpha_bits } => write!(f, "Palleted image with {}", alpha_bits),
+            Blp2Format::Raw1 {
+                alpha_bits,
+                options,
+            } => write!(f, "Palleted image with {}, {}", alpha_bits, options),
             Blp2Format::Raw3 => write!(f, "RGBA raw data"),
-            Blp2Format::Jpeg { has_alpha } => {
+            Blp2Format::Jpeg { has_alpha, options } => {
                 if *has_alpha {
-                    write!(f, "Jpeg image with alpha")
+                    write!(f, "Jpeg image with alpha, {}", options)
                 } else {
-                    write!(f, "Jpeg image without alpha")
+                    write!(f, "Jpeg image without alpha, {}", options)
                 }
             }
             Blp2Format::Dxt1 {
@@ -248,23 +602,87 @@ impl fmt::Display for Blp2Format {
     }
 }
 
-/// Convert from unpacked pixels into BLP image ready for writing down
+/// Convert from unpacked pixels into BLP image ready for writing down.
+/// Mipmaps are configured by [MipmapOptions] or just by [FilterType].
 pub fn image_to_blp(
     image: DynamicImage,
     make_mipmaps: bool,
     target: BlpTarget,
-    mipmap_filter: FilterType,
+    mipmap_options: impl Into<MipmapOptions>,
 ) -> Result<BlpImage, Error> {
-    if image.width() > BLP_MAX_WIDTH {
-        return Err(Error::WidthTooLarge(image.width()));
+    check_size(image.width(), image.height())?;
+    // Alpha of opaque JPEG shouldn't affect filtering of mipmaps
+    let image = if target.is_opaque_jpeg() {
+        DynamicImage::ImageRgb8(image.into_rgb8())
+    } else {
+        image
+    };
+    let levels = mipmap_levels(image, make_mipmaps, &mipmap_options.into())?;
+    levels_to_blp(levels, make_mipmaps, target, PaletteTraining::RootLevel)
+}
+
+/// Convert mipmaps made by hand into BLP image ready for writing down. The
+/// first image is the root level, the next ones should have sizes that
+/// [BlpHeader::mipmap_size] gives for the root size, down to 1x1. Single
+/// image produces BLP without mipmaps. RAW1 palette is trained on all levels
+/// unless [PaletteOptions::training] says otherwise, so colors painted only
+/// in mipmaps aren't mapped to unrelated palette entries. JPEG header is
+/// shared as usual.
+pub fn mip_chain_to_blp(levels: Vec<DynamicImage>, target: BlpTarget) -> Result<BlpImage, Error> {
+    let root = levels.first().ok_or(Error::MissingImage(0))?;
+    check_size(root.width(), root.height())?;
+    let make_mipmaps = levels.len() > 1;
+    if make_mipmaps {
+        let header = BlpHeader {
+            width: root.width(),
+            height: root.height(),
+            ..Default::default()
+        };
+        let expected = (header.mipmaps_count() + 1).min(16);
+        if levels.len() != expected {
+            return Err(Error::MipmapCountMismatch(expected, levels.len()));
+        }
+        for (i, level) in levels.iter().enumerate() {
+            let (width, height) = header.mipmap_size(i);
+            if (level.width(), level.height()) != (width, height) {
+                return Err(Error::MipmapSizeMismatch(
+                    i,
+                    width,
+                    height,
+                    level.width(),
+                    level.height(),
+                ));
+            }
+        }
+    }
+    levels_to_blp(levels, make_mipmaps, target, PaletteTraining::AllLevels)
+}
+
+fn check_size(width: u32, height: u32) -> Result<(), Error> {
+    if width > BLP_MAX_WIDTH {
+        return Err(Error::WidthTooLarge(width));
     }
-    if image.height() > BLP_MAX_HEIGHT {
-        return Err(Error::HeightTooLarge(image.height()));
+    if height > BLP_MAX_HEIGHT {
+        return Err(Error::HeightTooLarge(height));
     }
+    Ok(())
+}
 
+fn levels_to_blp(
+    levels: Vec<DynamicImage>,
+    make_mipmaps: bool,
+    target: BlpTarget,
+    default_training: PaletteTraining,
+) -> Result<BlpImage, Error> {
+    let root = levels.first().ok_or(Error::MissingImage(0))?;
+    let width = root.width();
+    let height = root.height();
     match target {
         BlpTarget::Blp0(format) => match format {
-            BlpOldFormat::Raw1 { alpha_bits } => {
+            BlpOldFormat::Raw1 {
+                alpha_bits,
+                options,
+            } => {
                 let header = BlpHeader {
                     version: BlpVersion::Blp0,
                     content: BlpContentTag::Direct,
@@ -273,20 +691,20 @@ pub fn image_to_blp(
                         extra: 4,
                         has_mipmaps: if make_mipmaps { 1 } else { 0 },
                     },
-                    width: image.width(),
-                    height: image.height(),
+                    width,
+                    height,
                     mipmap_locator: MipmapLocator::External,
                 };
                 let blp_raw1 =
-                    image_to_raw1(image, alpha_bits.into(), make_mipmaps, mipmap_filter)?;
+                    levels_to_raw1(levels, alpha_bits.into(), &options, default_training)?;
                 Ok(BlpImage {
                     header,
                     content: BlpContent::Raw1(blp_raw1),
                 })
             }
-            BlpOldFormat::Jpeg { has_alpha } => {
+            BlpOldFormat::Jpeg { has_alpha, options } => {
                 let alpha_bits = if has_alpha { 8 } else { 0 };
-                let blp_jpeg = image_to_jpeg(&image, make_mipmaps, alpha_bits, mipmap_filter)?;
+                let blp_jpeg = levels_to_jpeg(levels, alpha_bits, &options)?;
                 Ok(BlpImage {
                     header: BlpHeader {
                         version: BlpVersion::Blp0,
@@ -296,8 +714,8 @@ pub fn image_to_blp(
                             extra: 5,
                             has_mipmaps: if make_mipmaps { 1 } else { 0 },
                         },
-                        width: image.width(),
-                        height: image.height(),
+                        width,
+                        height,
                         mipmap_locator: MipmapLocator::External,
                     },
                     content: BlpContent::Jpeg(blp_jpeg),
@@ -305,11 +723,12 @@ pub fn image_to_blp(
             }
         },
         BlpTarget::Blp1(format) => match format {
-            BlpOldFormat::Raw1 { alpha_bits } => {
-                let width = image.width();
-                let height = image.height();
+            BlpOldFormat::Raw1 {
+                alpha_bits,
+                options,
+            } => {
                 let blp_raw1 =
-                    image_to_raw1(image, alpha_bits.into(), make_mipmaps, mipmap_filter)?;
+                    levels_to_raw1(levels, alpha_bits.into(), &options, default_training)?;
                 let header = BlpHeader {
                     version: BlpVersion::Blp1,
                     content: BlpContentTag::Direct,
@@ -327,9 +746,9 @@ pub fn image_to_blp(
                     content: BlpContent::Raw1(blp_raw1),
                 })
             }
-            BlpOldFormat::Jpeg { has_alpha } => {
+            BlpOldFormat::Jpeg { has_alpha, options } => {
                 let alpha_bits = if has_alpha { 8 } else { 0 };
-                let blp_jpeg = image_to_jpeg(&image, make_mipmaps, alpha_bits, mipmap_filter)?;
+                let blp_jpeg = levels_to_jpeg(levels, alpha_bits, &options)?;
                 Ok(BlpImage {
                     header: BlpHeader {
                         version: BlpVersion::Blp1,
@@ -339,27 +758,28 @@ pub fn image_to_blp(
                             extra: 5,
                             has_mipmaps: if make_mipmaps { 1 } else { 0 },
                         },
-                        width: image.width(),
-                        height: image.height(),
+                        width,
+                        height,
                         mipmap_locator: blp_jpeg.mipmap_locator(BlpVersion::Blp1),
                     },
                     content: BlpContent::Jpeg(blp_jpeg),
                 })
             }
         },
-        BlpTarget::Blp2(format) => match format {
-            Blp2Format::Raw1 { alpha_bits } => {
-                let width = image.width();
-                let height = image.height();
+        BlpTarget::Blp2(ref format) => match *format {
+            Blp2Format::Raw1 {
+                alpha_bits,
+                ref options,
+            } => {
                 let blp_raw1 =
-                    image_to_raw1(image, alpha_bits.into(), make_mipmaps, mipmap_filter)?;
+                    levels_to_raw1(levels, alpha_bits.into(), options, default_training)?;
                 let header = BlpHeader {
                     version: BlpVersion::Blp2,
                     content: BlpContentTag::Direct,
                     flags: BlpFlags::Blp2 {
                         compression: Compression::Raw1,
                         alpha_bits: alpha_bits.into(),
-                        alpha_type: 0,
+                        alpha_type: format.alpha_type(),
                         has_mipmaps: if make_mipmaps { 1 } else { 0 },
                     },
                     width,
@@ -372,9 +792,7 @@ pub fn image_to_blp(
                 })
             }
             Blp2Format::Raw3 => {
-                let width = image.width();
-                let height = image.height();
-                let blp_raw3 = image_to_raw3(image, make_mipmaps, mipmap_filter)?;
+                let blp_raw3 = levels_to_raw3(levels)?;
                 Ok(BlpImage {
                     header: BlpHeader {
                         version: BlpVersion::Blp2,
@@ -382,7 +800,7 @@ pub fn image_to_blp(
                         flags: BlpFlags::Blp2 {
                             compression: Compression::Raw3,
                             alpha_bits: 8,
-                            alpha_type: 0,
+                            alpha_type: format.alpha_type(),
                             has_mipmaps: if make_mipmaps { 1 } else { 0 },
                         },
                         width,
@@ -392,9 +810,9 @@ pub fn image_to_blp(
                     content: BlpContent::Raw3(blp_raw3),
                 })
             }
-            Blp2Format::Jpeg { has_alpha } => {
+            Blp2Format::Jpeg { has_alpha, options } => {
                 let alpha_bits = if has_alpha { 8 } else { 0 };
-                let blp_jpeg = image_to_jpeg(&image, make_mipmaps, alpha_bits, mipmap_filter)?;
+                let blp_jpeg = levels_to_jpeg(levels, alpha_bits, &options)?;
                 Ok(BlpImage {
                     header: BlpHeader {
                         version: BlpVersion::Blp2,
@@ -402,11 +820,11 @@ pub fn image_to_blp(
                         flags: BlpFlags::Blp2 {
                             compression: Compression::Jpeg,
                             alpha_bits: 8,
-                            alpha_type: 0,
+                            alpha_type: format.alpha_type(),
                             has_mipmaps: if make_mipmaps { 1 } else { 0 },
                         },
-                        width: image.width(),
-                        height: image.height(),
+                        width,
+                        height,
                         mipmap_locator: blp_jpeg.mipmap_locator(BlpVersion::Blp2),
                     },
                     content: BlpContent::Jpeg(blp_jpeg),
@@ -416,16 +834,8 @@ pub fn image_to_blp(
                 has_alpha,
                 compress_algorithm,
             } => {
-                let width = image.width();
-                let height = image.height();
                 let alpha_bits = if has_alpha { 1 } else { 0 };
-                let blp_dxtn = image_to_dxtn(
-                    image,
-                    DxtnFormat::Dxt1,
-                    make_mipmaps,
-                    mipmap_filter,
-                    compress_algorithm,
-                )?;
+                let blp_dxtn = levels_to_dxtn(levels, DxtnFormat::Dxt1, compress_algorithm)?;
                 Ok(BlpImage {
                     header: BlpHeader {
                         version: BlpVersion::Blp2,
@@ -433,7 +843,7 @@ pub fn image_to_blp(
                         flags: BlpFlags::Blp2 {
                             compression: Compression::Dxtc,
                             alpha_bits,
-                            alpha_type: 0,
+                            alpha_type: format.alpha_type(),
                             has_mipmaps: if make_mipmaps { 1 } else { 0 },
                         },
                         width,
@@ -447,16 +857,8 @@ pub fn image_to_blp(
                 has_alpha,
                 compress_algorithm,
             } => {
-                let width = image.width();
-                let height = image.height();
                 let alpha_bits = if has_alpha { 8 } else { 0 };
-                let blp_dxtn = image_to_dxtn(
-                    image,
-                    DxtnFormat::Dxt3,
-                    make_mipmaps,
-                    mipmap_filter,
-                    compress_algorithm,
-                )?;
+                let blp_dxtn = levels_to_dxtn(levels, DxtnFormat::Dxt3, compress_algorithm)?;
                 Ok(BlpImage {
                     header: BlpHeader {
                         version: BlpVersion::Blp2,
@@ -464,7 +866,7 @@ pub fn image_to_blp(
                         flags: BlpFlags::Blp2 {
                             compression: Compression::Dxtc,
                             alpha_bits,
-                            alpha_type: 1,
+                            alpha_type: format.alpha_type(),
                             has_mipmaps: if make_mipmaps { 1 } else { 0 },
                         },
                         width,
@@ -478,16 +880,8 @@ pub fn image_to_blp(
                 has_alpha,
                 compress_algorithm,
             } => {
-                let width = image.width();
-                let height = image.height();
                 let alpha_bits = if has_alpha { 8 } else { 0 };
-                let blp_dxtn = image_to_dxtn(
-                    image,
-                    DxtnFormat::Dxt5,
-                    make_mipmaps,
-                    mipmap_filter,
-                    compress_algorithm,
-                )?;
+                let blp_dxtn = levels_to_dxtn(levels, DxtnFormat::Dxt5, compress_algorithm)?;
                 Ok(BlpImage {
                     header: BlpHeader {
                         version: BlpVersion::Blp2,
@@ -495,7 +889,7 @@ pub fn image_to_blp(
                         flags: BlpFlags::Blp2 {
                             compression: Compression::Dxtc,
                             alpha_bits,
-                            alpha_type: 7,
+                            alpha_type: format.alpha_type(),
                             has_mipmaps: if make_mipmaps { 1 } else { 0 },
                         },
                         width,
@@ -508,3 +902,87 @@ pub fn image_to_blp(
         },
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encode::encode_blp;
+    use crate::parser::parse_blp;
+    use ::image::{Rgba, RgbaImage};
+    use test_log::test;
+
+    #[test]
+    fn test_mip_chain_to_blp() {
+        // Hand made mipmaps of different solid colors
+        let colors = [
+            [200, 40, 40, 255],
+            [40, 200, 40, 255],
+            [40, 40, 200, 255],
+            [200, 200, 40, 255],
+            [40, 200, 200, 255],
+        ];
+        let chain = || -> Vec<DynamicImage> {
+            colors
+                .iter()
+                .enumerate()
+                .map(|(i, color)| {
+                    let image =
+                        RgbaImage::from_pixel((16 >> i).max(1), (8 >> i).max(1), Rgba(*color));
+                    DynamicImage::ImageRgba8(image)
+                })
+                .collect()
+        };
+        let targets = [
+            BlpTarget::Blp1(BlpOldFormat::Raw1 {
+                alpha_bits: AlphaBits::Bit8,
+                options: Default::default(),
+            }),
+            BlpTarget::Blp1(BlpOldFormat::Jpeg {
+                has_alpha: false,
+                options: Default::default(),
+            }),
+            BlpTarget::Blp2(Blp2Format::Raw3),
+            BlpTarget::Blp2(Default::default()),
+        ];
+        for target in targets {
+            let blp = mip_chain_to_blp(chain(), target.clone()).expect("converted");
+            let bytes = encode_blp(&blp).expect("encoded");
+            let (_, parsed) = parse_blp(&bytes).expect("parsed");
+            assert_eq!(parsed.image_count(), colors.len(), "{}", target);
+            for (level, color) in colors.iter().enumerate() {
+                let image = blp_to_image(&parsed, level)
+                    .expect("converted")
+                    .into_rgba8();
+                assert_eq!(image.dimensions(), parsed.header.mipmap_size(level));
+                for (actual, expected) in image.get_pixel(0, 0).0.iter().zip(color) {
+                    assert!(
+                        (*actual as i32 - *expected as i32).abs() <= 8,
+                        "{} level {}: {:?} != {:?}",
+                        target,
+                        level,
+                        image.get_pixel(0, 0).0,
+                        color
+                    );
+                }
+            }
+        }
+
+        let target = BlpTarget::Blp2(Blp2Format::Raw3);
+        let mut short = chain();
+        short.pop();
+        assert!(matches!(
+            mip_chain_to_blp(short, target.clone()),
+            Err(Error::MipmapCountMismatch(5, 4))
+        ));
+        let mut wrong = chain();
+        wrong[2] = DynamicImage::ImageRgba8(RgbaImage::new(4, 4));
+        assert!(matches!(
+            mip_chain_to_blp(wrong, target.clone()),
+            Err(Error::MipmapSizeMismatch(2, 4, 2, 4, 4))
+        ));
+        assert!(matches!(
+            mip_chain_to_blp(vec![], target),
+            Err(Error::MissingImage(0))
+        ));
+    }
+}
@@ -1,43 +1,465 @@
+use super::dither::{dither, Dithering};
 use super::error::Error;
 use ::image::RgbaImage;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
 
-/// Create image and palette for rgb image
-pub fn quantize_rgba(
-    mut img: RgbaImage,
-) -> Result<(Vec<u8>, Vec<u32>, color_quant::NeuQuant), Error> {
-    let palette_size = 255; // last color in cmap is not used
-    let sample_fact = 10; // speed-quality factor. 1 is best quality, 30 is best perf
-
-    // zero alpha values
-    for pix in img.pixels_mut() {
-        pix[3] = 0;
-    }
-    // quantize
-    let nq = color_quant::NeuQuant::new(sample_fact, palette_size, img.as_raw());
-    let quantized: Vec<u8> = img.pixels().map(|pix| nq.index_of(&pix.0) as u8).collect();
-    // collect palette
-    let palette = nq
-        .color_map_rgb()
-        .chunks(3)
+/// Algorithm that builds a palette for paletted (RAW1) images. Implement it
+/// to plug a custom quantizer into [build_palette].
+pub trait Quantizer {
+    /// Build palette of at most `max_colors` colors that approximates
+    /// given pixels.
+    fn palette(&self, pixels: &[[u8; 3]], max_colors: usize) -> Vec<[u8; 3]>;
+}
+
+/// Amount of entries in color map of paletted (RAW1) images
+pub const CMAP_SIZE: usize = 256;
+
+/// Built-in palette quantizers that can be selected for RAW1 encoding
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PaletteQuantizer {
+    /// Neural network quantizer. Fast and the default one.
+    NeuQuant {
+        /// Speed-quality factor from 1 (best quality) to 30 (best performance)
+        sample_factor: i32,
+    },
+    /// Recursively split color space at median along the widest channel.
+    /// Keeps rare but distinct colors, good for UI icons.
+    MedianCut,
+    /// Refine median cut palette with k-means clustering. The slowest, but
+    /// gives the lowest error.
+    KMeans {
+        /// Number of refinement passes
+        iterations: u32,
+    },
+}
+
+impl Default for PaletteQuantizer {
+    fn default() -> Self {
+        PaletteQuantizer::NeuQuant { sample_factor: 10 }
+    }
+}
+
+/// Which mipmap levels the shared palette of RAW1 image is built from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub enum PaletteTraining {
+    /// Only the root image, mipmaps are mapped onto its palette
+    #[default]
+    RootLevel,
+    /// All levels combined. Each next level has 4 times less pixels, so its
+    /// pixels are counted twice as many times as pixels of the previous
+    /// level. That gives small mipmaps of high contrast textures their own
+    /// colors while the root image still dominates.
+    AllLevels,
+}
+
+impl fmt::Display for PaletteTraining {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PaletteTraining::RootLevel => write!(f, "trained on root level"),
+            PaletteTraining::AllLevels => write!(f, "trained on all levels"),
+        }
+    }
+}
+
+/// Pixels to build shared palette from according to [PaletteTraining]
+pub fn training_pixels(levels: &[RgbaImage], training: PaletteTraining) -> Vec<[u8; 3]> {
+    match training {
+        PaletteTraining::RootLevel => levels.iter().take(1).flat_map(rgb_pixels).collect(),
+        PaletteTraining::AllLevels => levels
+            .iter()
+            .enumerate()
+            .flat_map(|(i, level)| {
+                let weight = 1 << i;
+                rgb_pixels(level).flat_map(move |color| std::iter::repeat_n(color, weight))
+            })
+            .collect(),
+    }
+}
+
+impl fmt::Display for PaletteQuantizer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PaletteQuantizer::NeuQuant { sample_factor } => {
+                write!(f, "NeuQuant with sample factor {}", sample_factor)
+            }
+            PaletteQuantizer::MedianCut => write!(f, "median cut"),
+            PaletteQuantizer::KMeans { iterations } => {
+                write!(f, "k-means with {} iterations", iterations)
+            }
+        }
+    }
+}
+
+impl Quantizer for PaletteQuantizer {
+    fn palette(&self, pixels: &[[u8; 3]], max_colors: usize) -> Vec<[u8; 3]> {
+        match *self {
+            PaletteQuantizer::NeuQuant { sample_factor } => {
+                NeuQuantQuantizer { sample_factor }.palette(pixels, max_colors)
+            }
+            PaletteQuantizer::MedianCut => MedianCutQuantizer.palette(pixels, max_colors),
+            PaletteQuantizer::KMeans { iterations } => {
+                KMeansQuantizer { iterations }.palette(pixels, max_colors)
+            }
+        }
+    }
+}
+
+/// Quantizer that trains [color_quant::NeuQuant] network
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct NeuQuantQuantizer {
+    /// Speed-quality factor from 1 (best quality) to 30 (best performance)
+    pub sample_factor: i32,
+}
+
+impl Quantizer for NeuQuantQuantizer {
+    fn palette(&self, pixels: &[[u8; 3]], max_colors: usize) -> Vec<[u8; 3]> {
+        // Alpha is quantized separately, so it is zeroed for the network
+        let rgba: Vec<u8> = pixels
+            .iter()
+            .flat_map(|[r, g, b]| [*r, *g, *b, 0])
+            .collect();
+        let nq = color_quant::NeuQuant::new(self.sample_factor.clamp(1, 30), max_colors, &rgba);
+        nq.color_map_rgb()
+            .chunks(3)
+            .map(|col| [col[0], col[1], col[2]])
+            .collect()
+    }
+}
+
+/// Median cut quantizer
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct MedianCutQuantizer;
+
+impl Quantizer for MedianCutQuantizer {
+    fn palette(&self, pixels: &[[u8; 3]], max_colors: usize) -> Vec<[u8; 3]> {
+        median_cut(&histogram(pixels), max_colors)
+            .iter()
+            .map(|bucket| mean_color(bucket))
+            .collect()
+    }
+}
+
+/// K-means quantizer seeded with median cut palette
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct KMeansQuantizer {
+    /// Number of refinement passes
+    pub iterations: u32,
+}
+
+impl Quantizer for KMeansQuantizer {
+    fn palette(&self, pixels: &[[u8; 3]], max_colors: usize) -> Vec<[u8; 3]> {
+        let colors = histogram(pixels);
+        let mut palette: Vec<[u8; 3]> = median_cut(&colors, max_colors)
+            .iter()
+            .map(|bucket| mean_color(bucket))
+            .collect();
+        for _ in 0..self.iterations {
+            let mut sums = vec![([0u64; 3], 0u64); palette.len()];
+            for (color, count) in colors.iter() {
+                let (sum, total) = &mut sums[nearest_color(&palette, *color)];
+                for (s, c) in sum.iter_mut().zip(color) {
+                    *s += *c as u64 * count;
+                }
+                *total += count;
+            }
+            let mut changed = false;
+            for (entry, (sum, total)) in palette.iter_mut().zip(sums) {
+                if total == 0 {
+                    continue;
+                }
+                let mean = sum.map(|s| ((s + total / 2) / total) as u8);
+                changed |= *entry != mean;
+                *entry = mean;
+            }
+            if !changed {
+                break;
+            }
+        }
+        palette
+    }
+}
+
+/// Unique colors with amount of pixels of the color
+fn histogram(pixels: &[[u8; 3]]) -> Vec<([u8; 3], u64)> {
+    let mut counts: HashMap<[u8; 3], u64> = HashMap::new();
+    for pixel in pixels {
+        *counts.entry(*pixel).or_default() += 1;
+    }
+    let mut colors: Vec<([u8; 3], u64)> = counts.into_iter().collect();
+    // Make result independent of hash map ordering
+    colors.sort_unstable();
+    colors
+}
+
+fn median_cut(colors: &[([u8; 3], u64)], max_colors: usize) -> Vec<Vec<([u8; 3], u64)>> {
+    let mut buckets = vec![colors.to_vec()];
+    buckets.retain(|bucket| !bucket.is_empty());
+    while buckets.len() < max_colors {
+        // Split bucket with the widest channel range, weighted by pixels count
+        let widest = buckets
+            .iter()
+            .enumerate()
+            .filter(|(_, bucket)| bucket.len() > 1)
+            .map(|(i, bucket)| {
+                let (channel, range) = widest_channel(bucket);
+                let pixels: u64 = bucket.iter().map(|(_, count)| count).sum();
+                (i, channel, range as u64 * pixels)
+            })
+            .max_by_key(|(_, _, score)| *score);
+        let (index, channel, _) = match widest {
+            Some(widest) => widest,
+            None => break,
+        };
+        let mut bucket = buckets.swap_remove(index);
+        bucket.sort_unstable_by_key(|(color, _)| color[channel]);
+        let half = bucket.iter().map(|(_, count)| count).sum::<u64>() / 2;
+        let mut accumulated = 0;
+        let mut median = 1;
+        for (i, (_, count)) in bucket.iter().enumerate().take(bucket.len() - 1) {
+            accumulated += count;
+            median = i + 1;
+            if accumulated >= half {
+                break;
+            }
+        }
+        let upper = bucket.split_off(median);
+        buckets.push(bucket);
+        buckets.push(upper);
+    }
+    buckets
+}
+
+fn widest_channel(bucket: &[([u8; 3], u64)]) -> (usize, u8) {
+    (0..3)
+        .map(|channel| {
+            let min = bucket.iter().map(|(c, _)| c[channel]).min().unwrap_or(0);
+            let max = bucket.iter().map(|(c, _)| c[channel]).max().unwrap_or(0);
+            (channel, max - min)
+        })
+        .max_by_key(|(_, range)| *range)
+        .unwrap_or((0, 0))
+}
+
+fn mean_color(bucket: &[([u8; 3], u64)]) -> [u8; 3] {
+    let total: u64 = bucket.iter().map(|(_, count)| count).sum::<u64>().max(1);
+    let mut sum = [0u64; 3];
+    for (color, count) in bucket {
+        for (s, c) in sum.iter_mut().zip(color) {
+            *s += *c as u64 * count;
+        }
+    }
+    sum.map(|s| ((s + total / 2) / total) as u8)
+}
+
+/// Index of palette entry with the least squared distance to the color
+pub fn nearest_color(palette: &[[u8; 3]], color: [u8; 3]) -> usize {
+    palette
+        .iter()
+        .enumerate()
+        .min_by_key(|(_, entry)| {
+            entry
+                .iter()
+                .zip(color)
+                .map(|(a, b)| (*a as i32 - b as i32).pow(2))
+                .sum::<i32>()
+        })
+        .map(|(i, _)| i)
+        .unwrap_or(0)
+}
+
+/// Build palette of [CMAP_SIZE] colors for the pixels. If all colors fit
+/// into the palette, they are taken as is without quantization loss.
+pub fn build_palette(pixels: &[[u8; 3]], quantizer: &dyn Quantizer) -> Result<Vec<[u8; 3]>, Error> {
+    let colors = histogram(pixels);
+    let mut palette = if colors.len() <= CMAP_SIZE {
+        colors.into_iter().map(|(color, _)| color).collect()
+    } else {
+        quantizer.palette(pixels, CMAP_SIZE)
+    };
+    if palette.len() > CMAP_SIZE {
+        return Err(Error::PaletteWrongSize(palette.len()));
+    }
+    palette.resize(CMAP_SIZE, [0, 0, 0]);
+    Ok(palette)
+}
+
+/// Colors of image pixels
+pub fn rgb_pixels(img: &RgbaImage) -> impl Iterator<Item = [u8; 3]> + '_ {
+    img.pixels().map(|pix| [pix[0], pix[1], pix[2]])
+}
+
+/// Distance between neighbour colors that ordered dithering covers
+const ORDERED_SPREAD: f32 = 32.0;
+
+/// Map pixels to the nearest colors of existing palette
+pub fn quantize_rgba_known(
+    img: &RgbaImage,
+    palette: &[[u8; 3]],
+    dithering: Dithering,
+) -> Result<Vec<u8>, Error> {
+    // Dithering only adds noise when every color is in the palette
+    let known: HashSet<[u8; 3]> = palette.iter().copied().collect();
+    let dithering = if rgb_pixels(img).all(|color| known.contains(&color)) {
+        Dithering::None
+    } else {
+        dithering
+    };
+    let mut cache: HashMap<[u8; 3], u8> = HashMap::new();
+    let values: Vec<[f32; 3]> = img
+        .pixels()
+        .map(|pix| [pix[0] as f32, pix[1] as f32, pix[2] as f32])
+        .collect();
+    let quantized = dither(
+        img.width() as usize,
+        &values,
+        dithering,
+        ORDERED_SPREAD,
+        |value| {
+            let color = value.map(|v| v.round() as u8);
+            let index = *cache
+                .entry(color)
+                .or_insert_with(|| nearest_color(palette, color) as u8);
+            (index, palette[index as usize].map(|v| v as f32))
+        },
+    );
+    Ok(quantized)
+}
+
+/// Pack palette colors into BLP color map entries with `padding` in the
+/// unused high byte
+pub fn palette_to_cmap(palette: &[[u8; 3]], padding: u8) -> Vec<u32> {
+    palette
+        .iter()
         .map(|col| {
             let red = col[0] as u32;
             let green = (col[1] as u32) << 8;
             let blue = (col[2] as u32) << 16;
-            red + green + blue
+            red + green + blue + ((padding as u32) << 24)
         })
-        .collect();
+        .collect()
+}
 
-    Ok((quantized, palette, nq))
+/// The most common high (padding) byte of color map entries, to pass as
+/// [PaletteOptions::padding](super::PaletteOptions::padding) when the
+/// decoded image is encoded back.
+pub fn cmap_padding(cmap: &[u32]) -> u8 {
+    let mut counts = [0usize; 256];
+    for color in cmap {
+        counts[(color >> 24) as usize] += 1;
+    }
+    (0..=255u8)
+        .max_by_key(|padding| (counts[*padding as usize], std::cmp::Reverse(*padding)))
+        .unwrap_or(0)
 }
 
-pub fn quantize_rgba_known(
-    mut img: RgbaImage,
-    nq: &color_quant::NeuQuant,
-) -> Result<Vec<u8>, Error> {
-    // zero alpha values
-    for pix in img.pixels_mut() {
-        pix[3] = 0;
+/// Unpack BLP color map entries into palette colors
+pub fn cmap_to_palette(cmap: &[u32]) -> Vec<[u8; 3]> {
+    cmap.iter()
+        .map(|color| {
+            [
+                (color & 0xFF) as u8,
+                ((color >> 8) & 0xFF) as u8,
+                ((color >> 16) & 0xFF) as u8,
+            ]
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::image::Rgba;
+    use test_log::test;
+
+    fn gradient() -> Vec<[u8; 3]> {
+        (0..64 * 64)
+            .map(|i| [(i % 64 * 4) as u8, (i / 64 * 4) as u8, ((i % 7) * 30) as u8])
+            .collect()
+    }
+
+    fn error(pixels: &[[u8; 3]], palette: &[[u8; 3]]) -> u64 {
+        pixels
+            .iter()
+            .map(|pixel| {
+                let entry = palette[nearest_color(palette, *pixel)];
+                entry
+                    .iter()
+                    .zip(pixel)
+                    .map(|(a, b)| (*a as i64 - *b as i64).pow(2) as u64)
+                    .sum::<u64>()
+            })
+            .sum()
+    }
+
+    #[test]
+    fn test_quantizers_size() {
+        let pixels = gradient();
+        for quantizer in [
+            PaletteQuantizer::default(),
+            PaletteQuantizer::MedianCut,
+            PaletteQuantizer::KMeans { iterations: 4 },
+        ] {
+            let palette = quantizer.palette(&pixels, CMAP_SIZE);
+            assert!(palette.len() <= CMAP_SIZE, "{}", quantizer);
+            assert!(palette.len() > 200, "{}", quantizer);
+        }
+    }
+
+    #[test]
+    fn test_kmeans_refines_median_cut() {
+        let pixels = gradient();
+        let median = MedianCutQuantizer.palette(&pixels, 16);
+        let kmeans = KMeansQuantizer { iterations: 8 }.palette(&pixels, 16);
+        assert!(error(&pixels, &kmeans) <= error(&pixels, &median));
+    }
+
+    #[test]
+    fn test_dithering_average() {
+        // Flat color between palette entries keeps its average with dithering
+        let image = RgbaImage::from_pixel(64, 8, Rgba([76, 0, 0, 255]));
+        let palette = [[0, 0, 0], [32, 0, 0], [64, 0, 0], [96, 0, 0], [128, 0, 0]];
+        let average = |indices: &[u8]| {
+            indices
+                .iter()
+                .map(|i| palette[*i as usize][0] as f64)
+                .sum::<f64>()
+                / indices.len() as f64
+        };
+        let plain = quantize_rgba_known(&image, &palette, Dithering::None).expect("quantized");
+        assert_eq!(average(&plain), 64.0);
+        for dithering in [Dithering::FloydSteinberg, Dithering::Ordered] {
+            let dithered = quantize_rgba_known(&image, &palette, dithering).expect("quantized");
+            assert!((average(&dithered) - 76.0).abs() < 2.0, "{}", dithering);
+        }
+    }
+
+    #[test]
+    fn test_training_pixels() {
+        let levels = [
+            RgbaImage::from_pixel(4, 4, Rgba([1, 1, 1, 255])),
+            RgbaImage::from_pixel(2, 2, Rgba([2, 2, 2, 255])),
+            RgbaImage::from_pixel(1, 1, Rgba([3, 3, 3, 255])),
+        ];
+        let count =
+            |pixels: &[[u8; 3]], value: u8| pixels.iter().filter(|p| **p == [value; 3]).count();
+        let root = training_pixels(&levels, PaletteTraining::RootLevel);
+        assert_eq!(root, vec![[1; 3]; 16]);
+        let all = training_pixels(&levels, PaletteTraining::AllLevels);
+        assert_eq!((count(&all, 1), count(&all, 2), count(&all, 3)), (16, 8, 4));
+    }
+
+    #[test]
+    fn test_few_colors_exact() {
+        let colors = [[255, 0, 0], [0, 255, 0], [0, 0, 255], [10, 10, 10]];
+        let pixels: Vec<[u8; 3]> = (0..100).map(|i| colors[i % 4]).collect();
+        for quantizer in [
+            PaletteQuantizer::MedianCut,
+            PaletteQuantizer::KMeans { iterations: 4 },
+        ] {
+            let palette = quantizer.palette(&pixels, CMAP_SIZE);
+            assert_eq!(palette.len(), 4);
+            assert_eq!(error(&pixels, &palette), 0);
+        }
     }
-    let quantized: Vec<u8> = img.pixels().map(|pix| nq.index_of(&pix.0) as u8).collect();
-    Ok(quantized)
 }
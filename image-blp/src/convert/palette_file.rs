@@ -0,0 +1,270 @@
+use super::error::Error;
+use super::palette::{palette_to_cmap, CMAP_SIZE};
+use ::image::io::Reader as ImageReader;
+use std::io::Cursor;
+use std::path::Path;
+
+/// Load palette for [PaletteOptions::palette](super::PaletteOptions::palette)
+/// from a file. Supported formats are GIMP palette (`.gpl`), JASC-PAL and
+/// RIFF palettes (`.pal`), raw 768 bytes RGB triples (`.pal`, `.act`) and any
+/// image that can be decoded by [image] crate. Palettes with less than 256
+/// colors are padded with black.
+pub fn load_palette<Q>(path: Q) -> Result<Vec<u32>, Error>
+where
+    Q: AsRef<Path>,
+{
+    let bytes =
+        std::fs::read(&path).map_err(|e| Error::PaletteFileSystem(path.as_ref().to_owned(), e))?;
+    let extension = path
+        .as_ref()
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.to_lowercase());
+    match extension.as_deref() {
+        Some("gpl") | Some("pal") | Some("act") => parse_palette(&bytes),
+        _ => palette_from_image(&bytes),
+    }
+}
+
+/// Parse palette file content. Format is detected by the signature, data
+/// without known signature is treated as raw RGB triples.
+pub fn parse_palette(bytes: &[u8]) -> Result<Vec<u32>, Error> {
+    let colors = if bytes.starts_with(b"GIMP Palette") {
+        parse_gpl(bytes)?
+    } else if bytes.starts_with(b"JASC-PAL") {
+        parse_jasc(bytes)?
+    } else if bytes.starts_with(b"RIFF") {
+        parse_riff(bytes)?
+    } else {
+        parse_raw(bytes)?
+    };
+    make_cmap(colors)
+}
+
+/// Take palette from image. Images up to 256 pixels are read as swatches in
+/// row order, larger ones should contain at most 256 unique colors that are
+/// taken in order of appearance.
+pub fn palette_from_image(bytes: &[u8]) -> Result<Vec<u32>, Error> {
+    let image = ImageReader::new(Cursor::new(bytes))
+        .with_guessed_format()
+        .map_err(|e| Error::PaletteInvalid(e.to_string()))?
+        .decode()?
+        .into_rgb8();
+    let pixels = image.pixels().map(|pixel| pixel.0);
+    let colors = if image.width() * image.height() <= CMAP_SIZE as u32 {
+        pixels.collect()
+    } else {
+        let mut colors: Vec<[u8; 3]> = vec![];
+        for color in pixels {
+            if !colors.contains(&color) {
+                if colors.len() == CMAP_SIZE {
+                    return Err(Error::PaletteInvalid(format!(
+                        "image has more than {} colors",
+                        CMAP_SIZE
+                    )));
+                }
+                colors.push(color);
+            }
+        }
+        colors
+    };
+    make_cmap(colors)
+}
+
+fn make_cmap(colors: Vec<[u8; 3]>) -> Result<Vec<u32>, Error> {
+    if colors.is_empty() || colors.len() > CMAP_SIZE {
+        return Err(Error::PaletteInvalid(format!(
+            "expected 1 to {} colors, got {}",
+            CMAP_SIZE,
+            colors.len()
+        )));
+    }
+    let mut cmap = palette_to_cmap(&colors, 0);
+    cmap.resize(CMAP_SIZE, 0);
+    Ok(cmap)
+}
+
+fn parse_rgb<'a>(
+    mut components: impl Iterator<Item = &'a str>,
+    line: &str,
+) -> Result<[u8; 3], Error> {
+    let mut color = [0; 3];
+    for value in color.iter_mut() {
+        *value = components
+            .next()
+            .and_then(|c| c.parse().ok())
+            .ok_or_else(|| Error::PaletteInvalid(format!("invalid color '{}'", line)))?;
+    }
+    Ok(color)
+}
+
+fn text_lines(bytes: &[u8]) -> Result<impl Iterator<Item = &str>, Error> {
+    let text = std::str::from_utf8(bytes).map_err(|e| Error::PaletteInvalid(e.to_string()))?;
+    Ok(text.lines().map(|line| line.trim()))
+}
+
+/// GIMP palette: header with optional name and columns and then lines with
+/// `R G B name` per color.
+fn parse_gpl(bytes: &[u8]) -> Result<Vec<[u8; 3]>, Error> {
+    text_lines(bytes)?
+        .skip(1)
+        .filter(|line| {
+            !line.is_empty()
+                && !line.starts_with('#')
+                && !line.starts_with("Name:")
+                && !line.starts_with("Columns:")
+        })
+        .map(|line| parse_rgb(line.split_whitespace(), line))
+        .collect()
+}
+
+/// JASC-PAL: signature, version, amount of colors and then `R G B` lines
+fn parse_jasc(bytes: &[u8]) -> Result<Vec<[u8; 3]>, Error> {
+    let mut lines = text_lines(bytes)?.skip(2);
+    let count: usize = lines
+        .next()
+        .and_then(|line| line.parse().ok())
+        .ok_or_else(|| Error::PaletteInvalid("missing JASC-PAL colors count".to_owned()))?;
+    let colors = lines
+        .filter(|line| !line.is_empty())
+        .take(count)
+        .map(|line| parse_rgb(line.split_whitespace(), line))
+        .collect::<Result<Vec<_>, _>>()?;
+    if colors.len() != count {
+        return Err(Error::PaletteInvalid(format!(
+            "expected {} colors in JASC-PAL, got {}",
+            count,
+            colors.len()
+        )));
+    }
+    Ok(colors)
+}
+
+/// Microsoft RIFF palette: `data` chunk with version, amount of colors and
+/// `R G B flags` entries.
+fn parse_riff(bytes: &[u8]) -> Result<Vec<[u8; 3]>, Error> {
+    let invalid = || Error::PaletteInvalid("malformed RIFF palette".to_owned());
+    if bytes.get(8..12) != Some(&b"PAL "[..]) {
+        return Err(invalid());
+    }
+    let mut pos = 12;
+    while let Some(id) = bytes.get(pos..pos + 4) {
+        let size = bytes.get(pos + 4..pos + 8).ok_or_else(invalid)?;
+        let size = u32::from_le_bytes([size[0], size[1], size[2], size[3]]) as usize;
+        let chunk = bytes.get(pos + 8..pos + 8 + size).ok_or_else(invalid)?;
+        if id == b"data" {
+            let count = u16::from_le_bytes([
+                *chunk.get(2).ok_or_else(invalid)?,
+                *chunk.get(3).ok_or_else(invalid)?,
+            ]) as usize;
+            let entries = chunk.get(4..4 + count * 4).ok_or_else(invalid)?;
+            return Ok(entries
+                .chunks_exact(4)
+                .map(|entry| [entry[0], entry[1], entry[2]])
+                .collect());
+        }
+        // Chunks are aligned to 2 bytes
+        pos += 8 + size + size % 2;
+    }
+    Err(invalid())
+}
+
+/// Raw RGB triples, e.g. Adobe color table. The last 4 bytes of 772 bytes
+/// color table contain amount of colors.
+fn parse_raw(bytes: &[u8]) -> Result<Vec<[u8; 3]>, Error> {
+    let colors = CMAP_SIZE * 3;
+    let count = match bytes.len() {
+        len if len == colors => CMAP_SIZE,
+        len if len == colors + 4 => {
+            (u16::from_be_bytes([bytes[colors], bytes[colors + 1]]) as usize).clamp(1, CMAP_SIZE)
+        }
+        len => {
+            return Err(Error::PaletteInvalid(format!(
+                "unknown palette format of {} bytes",
+                len
+            )))
+        }
+    };
+    Ok(bytes[0..count * 3]
+        .chunks_exact(3)
+        .map(|rgb| [rgb[0], rgb[1], rgb[2]])
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::image::{ImageFormat, Rgb, RgbImage};
+    use test_log::test;
+
+    const RED: u32 = 0x0000FF;
+    const GREEN: u32 = 0x00FF00;
+    const BLUE: u32 = 0xFF0000;
+
+    fn check_cmap(cmap: &[u32]) {
+        assert_eq!(cmap.len(), CMAP_SIZE);
+        assert_eq!(cmap[0..3], [RED, GREEN, BLUE]);
+        assert!(cmap[3..].iter().all(|c| *c == 0));
+    }
+
+    #[test]
+    fn test_gpl() {
+        let gpl = "GIMP Palette\nName: Test\nColumns: 3\n#\n255   0   0\tRed\n  0 255   0\tGreen\n  0   0 255\tBlue\n";
+        check_cmap(&parse_palette(gpl.as_bytes()).expect("parsed"));
+    }
+
+    #[test]
+    fn test_jasc() {
+        let jasc = "JASC-PAL\r\n0100\r\n3\r\n255 0 0\r\n0 255 0\r\n0 0 255\r\n";
+        check_cmap(&parse_palette(jasc.as_bytes()).expect("parsed"));
+        let short = "JASC-PAL\n0100\n4\n255 0 0\n";
+        assert!(matches!(
+            parse_palette(short.as_bytes()),
+            Err(Error::PaletteInvalid(_))
+        ));
+    }
+
+    #[test]
+    fn test_riff() {
+        let mut riff = b"RIFF\x1C\x00\x00\x00PAL data\x10\x00\x00\x00\x00\x03\x03\x00".to_vec();
+        riff.extend([255, 0, 0, 0, 0, 255, 0, 0, 0, 0, 255, 0]);
+        check_cmap(&parse_palette(&riff).expect("parsed"));
+    }
+
+    #[test]
+    fn test_raw() {
+        let mut raw = vec![0; 768];
+        raw[0..9].copy_from_slice(&[255, 0, 0, 0, 255, 0, 0, 0, 255]);
+        check_cmap(&parse_palette(&raw).expect("parsed"));
+        raw.extend([0, 3, 0xFF, 0xFF]);
+        check_cmap(&parse_palette(&raw).expect("parsed"));
+        assert!(parse_palette(&[0; 10]).is_err());
+    }
+
+    #[test]
+    fn test_image_palette() {
+        let swatches = RgbImage::from_fn(3, 1, |x, _| match x {
+            0 => Rgb([255, 0, 0]),
+            1 => Rgb([0, 255, 0]),
+            _ => Rgb([0, 0, 255]),
+        });
+        let large =
+            ::image::imageops::resize(&swatches, 30, 20, ::image::imageops::FilterType::Nearest);
+        for image in [swatches, large] {
+            let mut png = vec![];
+            image
+                .write_to(&mut Cursor::new(&mut png), ImageFormat::Png)
+                .expect("encoded");
+            check_cmap(&palette_from_image(&png).expect("parsed"));
+        }
+
+        let dir = tempfile::tempdir().expect("temp dir");
+        let path = dir.path().join("palette.gpl");
+        std::fs::write(&path, "GIMP Palette\n255 0 0\n0 255 0\n0 0 255\n").expect("written");
+        check_cmap(&load_palette(&path).expect("loaded"));
+        assert!(matches!(
+            load_palette(dir.path().join("missing.png")),
+            Err(Error::PaletteFileSystem(_, _))
+        ));
+    }
+}
@@ -1,21 +1,19 @@
+use super::dither::{dither, Dithering};
 use super::error::Error;
-use super::mipmap::generate_mipmaps;
 use super::palette::*;
+use super::PaletteOptions;
 use crate::types::*;
-use ::image::{imageops::FilterType, DynamicImage, RgbImage, RgbaImage};
+use ::image::{DynamicImage, GrayImage, RgbImage, RgbaImage};
 
 pub fn raw1_to_image(
     header: &BlpHeader,
-    image: &BlpRaw1,
+    cmap: &[u32],
+    raw_image: Raw1ImageRef,
     mipmap_level: usize,
 ) -> Result<DynamicImage, Error> {
-    if mipmap_level >= image.images.len() {
-        return Err(Error::MissingImage(mipmap_level));
+    if cmap.len() != 256 {
+        return Err(Error::ColorMapLengthInvalid(cmap.len()));
     }
-    if image.cmap.len() != 256 {
-        return Err(Error::ColorMapLengthInvalid(image.cmap.len()));
-    }
-    let raw_image = &image.images[mipmap_level];
     let (width, height) = header.mipmap_size(mipmap_level);
     if (width as usize) * (height as usize) != raw_image.indexed_rgb.len() {
         return Err(Error::MismatchSizes(
@@ -26,200 +24,515 @@ pub fn raw1_to_image(
         ));
     }
     let alpha_bits = header.alpha_bits();
+    check_alpha_size(header, &raw_image, mipmap_level)?;
+    let color = |i: usize| {
+        let color = cmap[raw_image.indexed_rgb[i] as usize];
+        [
+            (color & 0xFF) as u8,
+            ((color >> 8) & 0xFF) as u8,
+            ((color >> 16) & 0xFF) as u8,
+        ]
+    };
+
     if alpha_bits == 0 {
         let mut res_image = RgbImage::new(width, height);
         for (i, pixel) in res_image.pixels_mut().enumerate() {
-            let ci = raw_image.indexed_rgb[i];
-            let color = image.cmap[ci as usize];
-            pixel.0[0] = (color & 0xFF) as u8;
-            pixel.0[1] = ((color >> 8) & 0xFF) as u8;
-            pixel.0[2] = ((color >> 16) & 0xFF) as u8;
+            pixel.0 = color(i);
         }
         Ok(DynamicImage::ImageRgb8(res_image))
-    } else if alpha_bits == 1 {
-        let alpha_values = raw_image.indexed_alpha.len() * 8;
-        // > as there can be padding bits
-        if (width as usize) * (height as usize) > alpha_values {
-            return Err(Error::MismatchSizes(
-                mipmap_level,
-                width,
-                height,
-                raw_image.indexed_rgb.len(),
-            ));
-        }
-
+    } else {
         let mut res_image = RgbaImage::new(width, height);
         for (i, pixel) in res_image.pixels_mut().enumerate() {
-            let ci = raw_image.indexed_rgb[i];
-            let color = image.cmap[ci as usize];
-            pixel.0[0] = (color & 0xFF) as u8;
-            pixel.0[1] = ((color >> 8) & 0xFF) as u8;
-            pixel.0[2] = ((color >> 16) & 0xFF) as u8;
-            pixel.0[3] = (raw_image.indexed_alpha[i / 8] >> (i % 8)) & 0x01;
+            let [r, g, b] = color(i);
+            let alpha = unpack_alpha(alpha_bits, raw_image.indexed_alpha, i);
+            pixel.0 = [r, g, b, expand_alpha(alpha_bits, alpha)];
         }
         Ok(DynamicImage::ImageRgba8(res_image))
-    } else if alpha_bits == 4 {
-        let alpha_values = raw_image.indexed_alpha.len() * 2;
-
-        // > as there can be padding bits
-        if (width as usize) * (height as usize) > alpha_values {
-            return Err(Error::MismatchSizes(
-                mipmap_level,
-                width,
-                height,
-                raw_image.indexed_rgb.len(),
-            ));
-        }
+    }
+}
 
-        let mut res_image = RgbaImage::new(width, height);
-        for (i, pixel) in res_image.pixels_mut().enumerate() {
-            let ci = raw_image.indexed_rgb[i];
-            let color = image.cmap[ci as usize];
-            pixel.0[0] = (color & 0xFF) as u8;
-            pixel.0[1] = ((color >> 8) & 0xFF) as u8;
-            pixel.0[2] = ((color >> 16) & 0xFF) as u8;
-            let alpha_block = raw_image.indexed_alpha[i / 2];
-            pixel.0[3] = if i % 2 == 0 {
-                alpha_block
-            } else {
-                alpha_block >> 4
-            };
-        }
-        Ok(DynamicImage::ImageRgba8(res_image))
-    } else if alpha_bits == 8 {
-        let alpha_values = raw_image.indexed_alpha.len();
-
-        if (width as usize) * (height as usize) != alpha_values {
-            return Err(Error::MismatchSizes(
-                mipmap_level,
-                width,
-                height,
-                raw_image.indexed_rgb.len(),
-            ));
-        }
+/// Extract alpha values as they are stored in the file: 0..=1 for 1 bit
+/// alpha, 0..=15 for 4 bits and 0..=255 for 8 bits.
+pub fn raw1_alpha_to_image(
+    header: &BlpHeader,
+    raw_image: Raw1ImageRef,
+    mipmap_level: usize,
+) -> Result<Option<GrayImage>, Error> {
+    let alpha_bits = header.alpha_bits();
+    check_alpha_size(header, &raw_image, mipmap_level)?;
+    if alpha_bits == 0 {
+        return Ok(None);
+    }
+    let (width, height) = header.mipmap_size(mipmap_level);
+    let mut res_image = GrayImage::new(width, height);
+    for (i, pixel) in res_image.pixels_mut().enumerate() {
+        pixel.0 = [unpack_alpha(alpha_bits, raw_image.indexed_alpha, i)];
+    }
+    Ok(Some(res_image))
+}
 
-        let mut res_image = RgbaImage::new(width, height);
-        for (i, pixel) in res_image.pixels_mut().enumerate() {
-            let ci = raw_image.indexed_rgb[i];
-            let color = image.cmap[ci as usize];
-            pixel.0[0] = (color & 0xFF) as u8;
-            pixel.0[1] = ((color >> 8) & 0xFF) as u8;
-            pixel.0[2] = ((color >> 16) & 0xFF) as u8;
-            pixel.0[3] = raw_image.indexed_alpha[i];
-        }
-        Ok(DynamicImage::ImageRgba8(res_image))
+fn check_alpha_size(
+    header: &BlpHeader,
+    raw_image: &Raw1ImageRef,
+    mipmap_level: usize,
+) -> Result<(), Error> {
+    let alpha_bits = header.alpha_bits();
+    if ![0, 1, 4, 8].contains(&alpha_bits) {
+        return Err(Error::Raw1InvalidAlphaBits(alpha_bits));
+    }
+    let (width, height) = header.mipmap_size(mipmap_level);
+    let pixels = (width as usize) * (height as usize);
+    let alpha_values = raw_image.indexed_alpha.len() * 8 / (alpha_bits.max(1) as usize);
+    // 1 and 4 bits alpha can have padding bits at the end
+    let wrong = if alpha_bits == 1 || alpha_bits == 4 {
+        pixels > alpha_values
     } else {
-        Err(Error::Raw1InvalidAlphaBits(alpha_bits))
+        alpha_bits == 8 && pixels != alpha_values
+    };
+    if wrong {
+        return Err(Error::MismatchAlphaSizes(
+            mipmap_level,
+            width,
+            height,
+            alpha_values,
+        ));
     }
+    Ok(())
 }
 
-pub fn image_to_raw1(
-    image: DynamicImage,
+/// Get alpha of i-th pixel at native precision. Pixels are packed starting
+/// from the lowest bits of byte.
+fn unpack_alpha(alpha_bits: u32, indexed_alpha: &[u8], i: usize) -> u8 {
+    match alpha_bits {
+        1 => (indexed_alpha[i / 8] >> (i % 8)) & 0x01,
+        4 => (indexed_alpha[i / 2] >> (4 * (i % 2))) & 0x0F,
+        8 => indexed_alpha[i],
+        _ => 0xFF,
+    }
+}
+
+/// Scale alpha from native precision to 0..=255
+fn expand_alpha(alpha_bits: u32, alpha: u8) -> u8 {
+    match alpha_bits {
+        1 => alpha * 0xFF,
+        4 => alpha * 0x11,
+        _ => alpha,
+    }
+}
+
+/// Scale alpha from 0..=255 to native precision with rounding to the
+/// nearest value, so [expand_alpha] gives the closest representable alpha.
+fn reduce_alpha(alpha_bits: u32, alpha: u8) -> u8 {
+    match alpha_bits {
+        1 => (alpha >= 0x80) as u8,
+        4 => ((alpha as u16 + 8) / 0x11) as u8,
+        _ => alpha,
+    }
+}
+
+pub fn levels_to_raw1(
+    levels: Vec<DynamicImage>,
     alpha_bits: u32,
-    make_mipmaps: bool,
-    mipmap_filter: FilterType,
+    options: &PaletteOptions,
+    default_training: PaletteTraining,
 ) -> Result<BlpRaw1, Error> {
-    let mut raw_images = if make_mipmaps {
-        generate_mipmaps(image, mipmap_filter)?.into_iter()
-    } else {
-        vec![image].into_iter()
+    let levels: Vec<RgbaImage> = levels.into_iter().map(|level| level.into_rgba8()).collect();
+    if levels.is_empty() {
+        return Err(Error::MissingImage(0));
+    }
+
+    // All levels share the same palette
+    let palette = match &options.palette {
+        Some(cmap) => {
+            if cmap.len() != CMAP_SIZE {
+                return Err(Error::ColorMapLengthInvalid(cmap.len()));
+            }
+            cmap_to_palette(cmap)
+        }
+        None => build_palette(
+            &training_pixels(&levels, options.training.unwrap_or(default_training)),
+            &options.quantizer,
+        )?,
     };
 
     let mut images = vec![];
-
-    // Create quantized image from the first image.
-    let root_image = raw_images
-        .next()
-        .ok_or(Error::MissingImage(0))?
-        .into_rgba8();
-    let indexed_alpha = index_alpha(&root_image, alpha_bits)?;
-    let (root_quantized, cmap, nq) = quantize_rgba(root_image)?;
-    if cmap.len() != 255 {
-        return Err(Error::PaletteWrongSize(cmap.len()));
-    }
-    images.push(Raw1Image {
-        indexed_rgb: root_quantized,
-        indexed_alpha,
-    });
-
-    // Quantize mipmaps
-    for image in raw_images {
-        let rgba = image.into_rgba8();
-        let indexed_alpha = index_alpha(&rgba, alpha_bits)?;
-        let quantized = quantize_rgba_known(rgba, &nq)?;
+    for level in levels.iter() {
+        let indexed_alpha = index_alpha(level, alpha_bits, options.dithering)?;
+        let indexed_rgb = quantize_rgba_known(level, &palette, options.dithering)?;
         images.push(Raw1Image {
-            indexed_rgb: quantized,
+            indexed_rgb,
             indexed_alpha,
         });
     }
 
+    let cmap = match &options.palette {
+        Some(cmap) => cmap.clone(),
+        None => palette_to_cmap(&palette, options.padding),
+    };
     Ok(BlpRaw1 { cmap, images })
 }
 
-fn index_alpha_1bit(image: &RgbaImage) -> Vec<u8> {
-    let pixels_number = (image.width() as usize) * (image.height() as usize);
-    let alpha_values = ((pixels_number as f64) / 8.0).ceil() as usize;
-    let mut res = Vec::with_capacity(alpha_values);
-    if pixels_number > 0 {
-        let mut bits = 0;
-        let mut i = 0;
-        res.push(0);
-        for pixel in image.pixels() {
-            if bits >= 8 {
-                bits = 0;
-                i += 1;
-                res.push(0);
-            }
-            res[i] |= if pixel[3] > 0 { 1 << bits } else { 0 };
-            bits += 1;
-        }
-    }
-
-    res
-}
-
-fn index_alpha_4bit(image: &RgbaImage) -> Vec<u8> {
+/// Pack alpha of 1 or 4 bits. The last byte is padded with zero bits.
+fn index_alpha_packed(image: &RgbaImage, alpha_bits: u32, dithering: Dithering) -> Vec<u8> {
     let pixels_number = (image.width() as usize) * (image.height() as usize);
-    let alpha_values = ((pixels_number as f64) / 2.0).ceil() as usize;
-    let mut res = Vec::with_capacity(alpha_values);
-    if pixels_number > 0 {
-        let mut bits = 0;
-        let mut i = 0;
-        res.push(0);
-        for pixel in image.pixels() {
-            if bits >= 8 {
-                bits = 0;
-                i += 1;
-                res.push(0);
-            }
-            let scaled_alpha = (((pixel[3] as f64) / 255.0) * 123.0) as u8;
-            res[i] |= scaled_alpha << bits;
-            bits += 4;
-        }
+    let per_byte = 8 / alpha_bits as usize;
+    let values: Vec<[f32; 1]> = image.pixels().map(|pixel| [pixel[3] as f32]).collect();
+    // Distance between neighbour representable alpha values
+    let spread = expand_alpha(alpha_bits, 1) as f32;
+    let reduced = dither(
+        image.width() as usize,
+        &values,
+        dithering,
+        spread,
+        |[alpha]| {
+            let reduced = reduce_alpha(alpha_bits, alpha.round() as u8);
+            (reduced, [expand_alpha(alpha_bits, reduced) as f32])
+        },
+    );
+    let mut res = vec![0; pixels_number.div_ceil(per_byte)];
+    for (i, alpha) in reduced.into_iter().enumerate() {
+        let shift = (i % per_byte) * alpha_bits as usize;
+        res[i / per_byte] |= alpha << shift;
     }
-
     res
 }
 
 fn index_alpha_8bit(image: &RgbaImage) -> Vec<u8> {
-    let pixels_number = (image.width() as usize) * (image.height() as usize);
-    let mut res = Vec::with_capacity(pixels_number);
-    for pixel in image.pixels() {
-        res.push(pixel[3]);
-    }
-    res
+    image.pixels().map(|pixel| pixel[3]).collect()
 }
 
-fn index_alpha(image: &RgbaImage, alpha_bits: u32) -> Result<Vec<u8>, Error> {
+fn index_alpha(image: &RgbaImage, alpha_bits: u32, dithering: Dithering) -> Result<Vec<u8>, Error> {
     if alpha_bits == 0 {
         Ok(vec![])
-    } else if alpha_bits == 1 {
-        Ok(index_alpha_1bit(image))
-    } else if alpha_bits == 4 {
-        Ok(index_alpha_4bit(image))
+    } else if alpha_bits == 1 || alpha_bits == 4 {
+        Ok(index_alpha_packed(image, alpha_bits, dithering))
     } else if alpha_bits == 8 {
         Ok(index_alpha_8bit(image))
     } else {
         Err(Error::Raw1InvalidAlphaBits(alpha_bits))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::super::mipmap::generate_mipmaps;
+    use super::super::{blp_to_image, blp_to_native_alpha, image_to_blp, AlphaBits};
+    use super::super::{BlpOldFormat, BlpTarget, PaletteQuantizer, PaletteTraining};
+    use super::*;
+    use crate::encode::encode_blp;
+    use crate::parser::parse_blp;
+    use ::image::{imageops::FilterType, Rgba};
+    use test_log::test;
+
+    fn round_trip(alpha_bits: AlphaBits, image: &RgbaImage) -> (RgbaImage, Option<GrayImage>) {
+        round_trip_with(alpha_bits, Default::default(), image)
+    }
+
+    fn round_trip_with(
+        alpha_bits: AlphaBits,
+        options: PaletteOptions,
+        image: &RgbaImage,
+    ) -> (RgbaImage, Option<GrayImage>) {
+        let target = BlpTarget::Blp1(BlpOldFormat::Raw1 {
+            alpha_bits,
+            options,
+        });
+        let blp = image_to_blp(
+            DynamicImage::ImageRgba8(image.clone()),
+            false,
+            target,
+            FilterType::Nearest,
+        )
+        .expect("converted");
+        let bytes = encode_blp(&blp).expect("encoded");
+        let (_, parsed) = parse_blp(&bytes).expect("parsed");
+        let decoded = blp_to_image(&parsed, 0).expect("decoded").into_rgba8();
+        let native = blp_to_native_alpha(&parsed, 0).expect("alpha");
+        (decoded, native)
+    }
+
+    // Odd amount of pixels to check padding of the last byte
+    fn test_image(alpha: impl Fn(u32) -> u8) -> RgbaImage {
+        RgbaImage::from_fn(7, 5, |x, y| {
+            Rgba([x as u8 * 30, y as u8 * 40, 90, alpha(y * 7 + x)])
+        })
+    }
+
+    fn alphas(image: &RgbaImage) -> Vec<u8> {
+        image.pixels().map(|p| p[3]).collect()
+    }
+
+    #[test]
+    fn test_alpha_1bit() {
+        let image = test_image(|i| if i % 3 == 0 { 0 } else { 255 });
+        let (decoded, native) = round_trip(AlphaBits::Bit1, &image);
+        assert_eq!(alphas(&decoded), alphas(&image));
+        let native = native.expect("native alpha");
+        assert!(native
+            .pixels()
+            .zip(image.pixels())
+            .all(|(n, p)| n[0] * 255 == p[3]));
+
+        let rounded = round_trip(AlphaBits::Bit1, &test_image(|i| i as u8 * 7)).0;
+        let expected: Vec<u8> = (0..35)
+            .map(|i| if i * 7 >= 128 { 255 } else { 0 })
+            .collect();
+        assert_eq!(alphas(&rounded), expected);
+    }
+
+    #[test]
+    fn test_alpha_4bit() {
+        let image = test_image(|i| (i % 16) as u8 * 17);
+        let (decoded, native) = round_trip(AlphaBits::Bit4, &image);
+        assert_eq!(alphas(&decoded), alphas(&image));
+        let native = native.expect("native alpha");
+        let expected: Vec<u8> = (0..35).map(|i| i % 16).collect();
+        assert_eq!(native.into_raw(), expected);
+
+        let rounded = round_trip(AlphaBits::Bit4, &test_image(|i| i as u8 * 7)).0;
+        let expected: Vec<u8> = (0..35u8).map(|i| (i * 7 + 8) / 17 * 17).collect();
+        assert_eq!(alphas(&rounded), expected);
+    }
+
+    #[test]
+    fn test_alpha_8bit() {
+        let image = test_image(|i| i as u8 * 7);
+        let (decoded, native) = round_trip(AlphaBits::Bit8, &image);
+        assert_eq!(alphas(&decoded), alphas(&image));
+        assert_eq!(native.expect("native alpha").into_raw(), alphas(&image));
+    }
+
+    #[test]
+    fn test_no_alpha() {
+        let image = test_image(|_| 255);
+        let (decoded, native) = round_trip(AlphaBits::NoAlpha, &image);
+        assert_eq!(alphas(&decoded), alphas(&image));
+        assert!(native.is_none());
+    }
+
+    #[test]
+    fn test_quantizers() {
+        // More colors than the palette can hold
+        let image = RgbaImage::from_fn(32, 32, |x, y| {
+            Rgba([x as u8 * 8, y as u8 * 8, (x + y) as u8 * 4, 255])
+        });
+        for quantizer in [
+            PaletteQuantizer::default(),
+            PaletteQuantizer::MedianCut,
+            PaletteQuantizer::KMeans { iterations: 4 },
+        ] {
+            let options = PaletteOptions {
+                quantizer,
+                ..Default::default()
+            };
+            let (decoded, _) = round_trip_with(AlphaBits::NoAlpha, options, &image);
+            let error: i32 = decoded
+                .pixels()
+                .zip(image.pixels())
+                .flat_map(|(a, b)| a.0.into_iter().zip(b.0))
+                .map(|(a, b)| (a as i32 - b as i32).abs())
+                .sum();
+            let mean_error = error as f64 / (32 * 32 * 4) as f64;
+            assert!(mean_error < 8.0, "{} {}", quantizer, mean_error);
+        }
+    }
+
+    #[test]
+    fn test_exact_palette() {
+        // 256 unique colors fit the palette without loss
+        let image = RgbaImage::from_fn(16, 16, |x, y| Rgba([x as u8 * 17, y as u8 * 17, 200, 255]));
+        for dithering in [
+            Dithering::None,
+            Dithering::FloydSteinberg,
+            Dithering::Ordered,
+        ] {
+            let options = PaletteOptions {
+                dithering,
+                ..Default::default()
+            };
+            let (decoded, _) = round_trip_with(AlphaBits::NoAlpha, options, &image);
+            assert_eq!(decoded, image);
+        }
+    }
+
+    #[test]
+    fn test_palette_training() {
+        // Root level is red and blue stripes, mipmaps average them to purple
+        let image = RgbaImage::from_fn(64, 64, |x, y| {
+            if x % 2 == 0 {
+                Rgba([255, (y * 2) as u8, 0, 255])
+            } else {
+                Rgba([0, (y * 2) as u8, 255, 255])
+            }
+        });
+        let levels = generate_mipmaps(
+            DynamicImage::ImageRgba8(image),
+            &FilterType::Triangle.into(),
+        )
+        .expect("mipmaps");
+        let mipmap_error = |training| {
+            let options = PaletteOptions {
+                training: Some(training),
+                ..Default::default()
+            };
+            let raw1 = levels_to_raw1(levels.clone(), 0, &options, PaletteTraining::RootLevel)
+                .expect("converted");
+            let palette = cmap_to_palette(&raw1.cmap);
+            let level = levels[1].to_rgba8();
+            level
+                .pixels()
+                .zip(raw1.images[1].indexed_rgb.iter())
+                .flat_map(|(pixel, index)| pixel.0.into_iter().zip(palette[*index as usize]))
+                .map(|(a, b)| (a as i32 - b as i32).abs())
+                .sum::<i32>()
+        };
+        assert!(
+            mipmap_error(PaletteTraining::AllLevels) < mipmap_error(PaletteTraining::RootLevel)
+        );
+    }
+
+    #[test]
+    fn test_cmap_padding() {
+        let cmap: Vec<u32> = (0..256).map(|i| 0xAB000000 | (i * 0x010101)).collect();
+        let options = PaletteOptions {
+            palette: Some(cmap.clone()),
+            ..Default::default()
+        };
+        let target = BlpTarget::Blp1(BlpOldFormat::Raw1 {
+            alpha_bits: AlphaBits::NoAlpha,
+            options,
+        });
+        let blp = image_to_blp(
+            DynamicImage::ImageRgba8(test_image(|_| 255)),
+            true,
+            target,
+            FilterType::Nearest,
+        )
+        .expect("converted");
+        let bytes = encode_blp(&blp).expect("encoded");
+        let (_, parsed) = parse_blp(&bytes).expect("parsed");
+        assert_eq!(parsed.content, blp.content);
+        assert_eq!(encode_blp(&parsed).expect("encoded"), bytes);
+        match parsed.content {
+            BlpContent::Raw1(raw1) => assert_eq!(raw1.cmap, cmap),
+            _ => panic!("expected RAW1 content"),
+        }
+    }
+
+    #[test]
+    fn test_cmap_padding_requantized() {
+        // Source BLP with non zero padding byte in its color map
+        let cmap: Vec<u32> = (0..256).map(|i| 0xAB000000 | (i * 0x010101)).collect();
+        let source = image_to_blp(
+            DynamicImage::ImageRgba8(test_image(|_| 255)),
+            false,
+            BlpTarget::Blp1(BlpOldFormat::Raw1 {
+                alpha_bits: AlphaBits::NoAlpha,
+                options: PaletteOptions {
+                    palette: Some(cmap),
+                    ..Default::default()
+                },
+            }),
+            FilterType::Nearest,
+        )
+        .expect("converted");
+        let (_, source) = parse_blp(&encode_blp(&source).expect("encoded")).expect("parsed");
+        let padding = match &source.content {
+            BlpContent::Raw1(raw1) => cmap_padding(&raw1.cmap),
+            _ => panic!("expected RAW1 content"),
+        };
+        assert_eq!(padding, 0xAB);
+
+        // Decoded image goes through the quantizer and gets a new palette
+        let options = PaletteOptions {
+            padding,
+            ..Default::default()
+        };
+        let blp = image_to_blp(
+            blp_to_image(&source, 0).expect("decoded"),
+            false,
+            BlpTarget::Blp1(BlpOldFormat::Raw1 {
+                alpha_bits: AlphaBits::NoAlpha,
+                options,
+            }),
+            FilterType::Nearest,
+        )
+        .expect("converted");
+        let bytes = encode_blp(&blp).expect("encoded");
+        let offset = BlpHeader::size(BlpVersion::Blp1);
+        let cmap_bytes = &bytes[offset..offset + CMAP_SIZE * 4];
+        assert!(cmap_bytes.chunks(4).all(|entry| entry[3] == 0xAB));
+    }
+
+    #[test]
+    fn test_fixed_palette() {
+        let image = test_image(|_| 255);
+        let mut cmap: Vec<u32> = (0..256).map(|i| i * 0x010101).collect();
+        cmap[7] = 90 << 16;
+        let options = PaletteOptions {
+            palette: Some(cmap.clone()),
+            ..Default::default()
+        };
+        let target = BlpTarget::Blp1(BlpOldFormat::Raw1 {
+            alpha_bits: AlphaBits::NoAlpha,
+            options,
+        });
+        let blp = image_to_blp(
+            DynamicImage::ImageRgba8(image),
+            true,
+            target,
+            FilterType::Nearest,
+        )
+        .expect("converted");
+        let raw1 = match &blp.content {
+            BlpContent::Raw1(raw1) => raw1,
+            _ => panic!("expected RAW1 content"),
+        };
+        assert_eq!(raw1.cmap, cmap);
+        // Black with blue 90 is the nearest for the top left pixel
+        assert_eq!(raw1.images[0].indexed_rgb[0], 7);
+
+        let short = BlpTarget::Blp1(BlpOldFormat::Raw1 {
+            alpha_bits: AlphaBits::NoAlpha,
+            options: PaletteOptions {
+                palette: Some(vec![0; 10]),
+                ..Default::default()
+            },
+        });
+        let res = image_to_blp(
+            DynamicImage::ImageRgba8(test_image(|_| 255)),
+            false,
+            short,
+            FilterType::Nearest,
+        );
+        assert!(matches!(res, Err(Error::ColorMapLengthInvalid(10))));
+    }
+
+    #[test]
+    fn test_alpha_dithering() {
+        let image = RgbaImage::from_pixel(16, 16, Rgba([0, 0, 0, 64]));
+        for dithering in [Dithering::FloydSteinberg, Dithering::Ordered] {
+            let options = PaletteOptions {
+                dithering,
+                ..Default::default()
+            };
+            let (decoded, _) = round_trip_with(AlphaBits::Bit1, options, &image);
+            let opaque = decoded.pixels().filter(|p| p[3] == 255).count();
+            // A quarter of pixels is opaque to keep average transparency
+            assert!((60..=68).contains(&opaque), "{} {}", dithering, opaque);
+        }
+    }
+
+    #[test]
+    fn test_pack_unpack() {
+        let image = test_image(|i| (i % 16) as u8 * 17);
+        for alpha_bits in [1, 4, 8] {
+            let packed = index_alpha(&image, alpha_bits, Dithering::None).expect("packed");
+            assert_eq!(packed.len(), (35 * alpha_bits as usize).div_ceil(8));
+            for (i, pixel) in image.pixels().enumerate() {
+                let alpha = unpack_alpha(alpha_bits, &packed, i);
+                assert_eq!(alpha, reduce_alpha(alpha_bits, pixel[3]));
+            }
+        }
+    }
+}
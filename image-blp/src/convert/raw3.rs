@@ -1,30 +1,24 @@
 use super::error::Error;
-use super::mipmap::generate_mipmaps;
 use crate::types::*;
-use ::image::{imageops::FilterType, DynamicImage, RgbaImage};
+use ::image::{DynamicImage, RgbaImage};
 
 pub fn raw3_to_image(
     header: &BlpHeader,
-    image: &BlpRaw3,
+    pixels: impl ExactSizeIterator<Item = u32>,
     mipmap_level: usize,
 ) -> Result<DynamicImage, Error> {
-    if mipmap_level >= image.images.len() {
-        return Err(Error::MissingImage(mipmap_level));
-    }
-    let raw_image = &image.images[mipmap_level];
     let (width, height) = header.mipmap_size(mipmap_level);
-    if (width as usize) * (height as usize) != raw_image.pixels.len() {
+    if (width as usize) * (height as usize) != pixels.len() {
         return Err(Error::MismatchSizes(
             mipmap_level,
             width,
             height,
-            raw_image.pixels.len(),
+            pixels.len(),
         ));
     }
 
     let mut res_image = RgbaImage::new(width, height);
-    for (i, pixel) in res_image.pixels_mut().enumerate() {
-        let color = raw_image.pixels[i];
+    for (pixel, color) in res_image.pixels_mut().zip(pixels) {
         let blue = (color & 0xFF) as u8;
         let green = ((color >> 8) & 0xFF) as u8;
         let red = ((color >> 16) & 0xFF) as u8;
@@ -34,19 +28,9 @@ pub fn raw3_to_image(
     Ok(DynamicImage::ImageRgba8(res_image))
 }
 
-pub fn image_to_raw3(
-    image: DynamicImage,
-    make_mipmaps: bool,
-    mipmap_filter: FilterType,
-) -> Result<BlpRaw3, Error> {
-    let raw_images = if make_mipmaps {
-        generate_mipmaps(image, mipmap_filter)?.into_iter()
-    } else {
-        vec![image].into_iter()
-    };
-
+pub fn levels_to_raw3(levels: Vec<DynamicImage>) -> Result<BlpRaw3, Error> {
     let mut images = vec![];
-    for image in raw_images {
+    for image in levels {
         let rgba = image.into_rgba8();
         let pixels_num = (rgba.width() as usize) * (rgba.height() as usize);
         let mut pixels = Vec::with_capacity(pixels_num);
@@ -0,0 +1,155 @@
+use super::error::Error;
+use super::{AlphaBits, Blp2Format};
+use crate::types::*;
+
+/// Move content of BLP image into another version of the format without
+/// decoding of pixels, so there is no quality loss. The header flags and the
+/// mipmap locator are rebuilt for the new version.
+///
+/// JPEG and RAW1 content is supported by all versions, RAW3 and DXTn only by
+/// BLP2. Repacking into the same version rebuilds offsets of mipmaps.
+pub fn repack_blp(image: BlpImage, version: BlpVersion) -> Result<BlpImage, Error> {
+    let content_name = match &image.content {
+        BlpContent::Jpeg(_) => "JPEG",
+        BlpContent::Raw1(_) => "RAW1",
+        BlpContent::Raw3(_) => "RAW3",
+        BlpContent::Dxt1(_) => "DXT1",
+        BlpContent::Dxt3(_) => "DXT3",
+        BlpContent::Dxt5(_) => "DXT5",
+    };
+    let is_raw1 = matches!(image.content, BlpContent::Raw1(_));
+    let header = &image.header;
+    let has_mipmaps = header.has_mipmaps();
+    let alpha_bits = header.alpha_bits();
+
+    let flags = match (version, header.flags.clone()) {
+        (BlpVersion::Blp2, flags @ BlpFlags::Blp2 { .. }) => flags,
+        (BlpVersion::Blp0 | BlpVersion::Blp1, flags @ BlpFlags::Old { .. }) => flags,
+        (BlpVersion::Blp2, BlpFlags::Old { .. }) => {
+            // Only the variant matters for alpha_type, not the settings
+            let (compression, format) = if is_raw1 {
+                let format = Blp2Format::Raw1 {
+                    alpha_bits: AlphaBits::NoAlpha,
+                    options: Default::default(),
+                };
+                (Compression::Raw1, format)
+            } else {
+                let format = Blp2Format::Jpeg {
+                    has_alpha: alpha_bits > 0,
+                    options: Default::default(),
+                };
+                (Compression::Jpeg, format)
+            };
+            BlpFlags::Blp2 {
+                compression,
+                alpha_bits: alpha_bits.min(u8::MAX as u32) as u8,
+                alpha_type: format.alpha_type(),
+                has_mipmaps: has_mipmaps as u8,
+            }
+        }
+        (_, BlpFlags::Blp2 { .. }) => {
+            if !is_raw1 && image.content.tag() != BlpContentTag::Jpeg {
+                return Err(Error::RepackUnsupported(content_name, version));
+            }
+            BlpFlags::Old {
+                alpha_bits,
+                extra: if is_raw1 { 4 } else { 5 },
+                has_mipmaps: has_mipmaps as u32,
+            }
+        }
+    };
+
+    let mipmap_locator = if version == BlpVersion::Blp0 {
+        MipmapLocator::External
+    } else {
+        match &image.content {
+            BlpContent::Jpeg(content) => content.mipmap_locator(version),
+            BlpContent::Raw1(content) => content.mipmap_locator(version),
+            BlpContent::Raw3(content) => content.mipmap_locator(version),
+            BlpContent::Dxt1(content) | BlpContent::Dxt3(content) | BlpContent::Dxt5(content) => {
+                content.mipmap_locator(version)
+            }
+        }
+    };
+
+    Ok(BlpImage {
+        header: BlpHeader {
+            version,
+            content: image.content.tag(),
+            flags,
+            width: header.width,
+            height: header.height,
+            mipmap_locator,
+        },
+        content: image.content,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{BlpOldFormat, BlpTarget};
+    use super::*;
+    use crate::encode::{encode_blp, encode_blp0};
+    use crate::parser::parse_blp;
+    use crate::test_util::{image_blp, test_image};
+    use test_log::test;
+
+    fn test_blp(target: BlpTarget) -> BlpImage {
+        image_blp(test_image(32, 16, 200), true, target)
+    }
+
+    #[test]
+    fn test_repack_jpeg() {
+        let blp1 = test_blp(BlpTarget::Blp1(Default::default()));
+        let bytes = encode_blp(&blp1).expect("encoded");
+
+        let blp0 = repack_blp(blp1.clone(), BlpVersion::Blp0).expect("repacked");
+        assert_eq!(blp0.header.mipmap_locator, MipmapLocator::External);
+        assert_eq!(blp0.content, blp1.content);
+        let external = encode_blp0(&blp0).expect("encoded");
+        assert_eq!(external.blp_mipmaps.len(), blp1.image_count());
+
+        let blp2 = repack_blp(blp0, BlpVersion::Blp2).expect("repacked");
+        let (_, parsed) = parse_blp(&encode_blp(&blp2).expect("encoded")).expect("parsed");
+        assert_eq!(parsed.content, blp1.content);
+
+        let back = repack_blp(parsed, BlpVersion::Blp1).expect("repacked");
+        assert_eq!(encode_blp(&back).expect("encoded"), bytes);
+    }
+
+    #[test]
+    fn test_repack_raw1() {
+        let blp1 = test_blp(BlpTarget::Blp1(BlpOldFormat::Raw1 {
+            alpha_bits: AlphaBits::Bit4,
+            options: Default::default(),
+        }));
+        let blp2 = repack_blp(blp1.clone(), BlpVersion::Blp2).expect("repacked");
+        match blp2.header.flags {
+            BlpFlags::Blp2 {
+                compression,
+                alpha_bits,
+                alpha_type,
+                ..
+            } => assert_eq!(
+                (compression, alpha_bits, alpha_type),
+                (Compression::Raw1, 4, 8)
+            ),
+            _ => panic!("expected BLP2 flags"),
+        }
+        let back = repack_blp(blp2, BlpVersion::Blp1).expect("repacked");
+        assert_eq!(back, blp1);
+    }
+
+    #[test]
+    fn test_repack_blp2_only() {
+        let blp = test_blp(BlpTarget::Blp2(Blp2Format::Raw3));
+        let same = repack_blp(blp.clone(), BlpVersion::Blp2).expect("repacked");
+        assert_eq!(same, blp);
+        for version in [BlpVersion::Blp0, BlpVersion::Blp1] {
+            assert!(matches!(
+                repack_blp(blp.clone(), version),
+                Err(Error::RepackUnsupported("RAW3", _))
+            ));
+        }
+    }
+}
@@ -0,0 +1,251 @@
+use super::encode_blp;
+use super::error::Error;
+use crate::convert::{
+    image_to_blp, BlpTarget, Error as ConvertError, FilterType, MipmapColorSpace, MipmapOptions,
+};
+use ::image::{
+    error::{
+        EncodingError, ImageFormatHint, ParameterError, ParameterErrorKind, UnsupportedError,
+        UnsupportedErrorKind,
+    },
+    ColorType, DynamicImage, ImageBuffer, ImageEncoder, ImageError, ImageResult, Pixel,
+};
+use std::io::Write;
+
+/// Implementation of [ImageEncoder] for BLP files. That allows to
+/// save images into BLP with generic code of [image](https://crates.io/crates/image)
+/// crate. Internally it is [image_to_blp] followed by [encode_blp].
+///
+/// BLP0 target is not supported as it requires to write mipmaps into
+/// external files, use [save_blp](super::save_blp) for it.
+///
+/// ```no_run
+/// # use ::image::{DynamicImage, ImageEncoder};
+/// # use image_blp::{
+/// #     convert::{AlphaBits, BlpOldFormat, BlpTarget, FilterType},
+/// #     encode::BlpEncoder,
+/// # };
+/// # let image = DynamicImage::new_rgba8(64, 64);
+/// let file = std::fs::File::create("output.blp").expect("created");
+/// let encoder = BlpEncoder::new(file)
+///     .with_target(BlpTarget::Blp1(BlpOldFormat::Raw1 {
+///         alpha_bits: AlphaBits::Bit8,
+///         options: Default::default(),
+///     }))
+///     .with_mipmap_filter(FilterType::Nearest);
+/// encoder
+///     .write_image(image.as_bytes(), image.width(), image.height(), image.color())
+///     .expect("saved");
+/// ```
+pub struct BlpEncoder<W> {
+    writer: W,
+    target: BlpTarget,
+    make_mipmaps: bool,
+    mipmap_options: MipmapOptions,
+}
+
+impl<W: Write> BlpEncoder<W> {
+    /// Create encoder with default target ([BlpTarget::default]), generation of
+    /// mipmaps and [FilterType::Lanczos3] filter for them.
+    pub fn new(writer: W) -> Self {
+        BlpEncoder {
+            writer,
+            target: Default::default(),
+            make_mipmaps: true,
+            mipmap_options: Default::default(),
+        }
+    }
+
+    /// Select which BLP version and compression to produce
+    pub fn with_target(mut self, target: BlpTarget) -> Self {
+        self.target = target;
+        self
+    }
+
+    /// Whether to generate mipmaps for the image
+    pub fn with_mipmaps(mut self, make_mipmaps: bool) -> Self {
+        self.make_mipmaps = make_mipmaps;
+        self
+    }
+
+    /// Which algorithm to use to scale mipmaps down
+    pub fn with_mipmap_filter(mut self, mipmap_filter: FilterType) -> Self {
+        self.mipmap_options.filter = mipmap_filter;
+        self
+    }
+
+    /// Color space to scale mipmaps down in
+    pub fn with_mipmap_color_space(mut self, color_space: MipmapColorSpace) -> Self {
+        self.mipmap_options.color_space = color_space;
+        self
+    }
+
+    /// Whether to filter mipmaps with colors weighted by alpha
+    pub fn with_premultiplied_alpha(mut self, premultiplied_alpha: bool) -> Self {
+        self.mipmap_options.premultiplied_alpha = premultiplied_alpha;
+        self
+    }
+
+    /// Whether to fill colors of fully transparent pixels from the visible ones
+    pub fn with_alpha_bleed(mut self, alpha_bleed: bool) -> Self {
+        self.mipmap_options.alpha_bleed = alpha_bleed;
+        self
+    }
+
+    /// Alpha test threshold to preserve alpha coverage of mipmaps at
+    pub fn with_coverage_threshold(mut self, coverage_threshold: Option<u8>) -> Self {
+        self.mipmap_options.coverage_threshold = coverage_threshold;
+        self
+    }
+}
+
+impl<W: Write> ImageEncoder for BlpEncoder<W> {
+    fn write_image(
+        mut self,
+        buf: &[u8],
+        width: u32,
+        height: u32,
+        color_type: ColorType,
+    ) -> ImageResult<()> {
+        if let BlpTarget::Blp0(_) = self.target {
+            return Err(ImageError::Unsupported(
+                UnsupportedError::from_format_and_kind(
+                    blp_hint(),
+                    UnsupportedErrorKind::GenericFeature("BLP0 with external mipmaps".to_owned()),
+                ),
+            ));
+        }
+        let image = make_image(buf, width, height, color_type)?;
+        let blp = image_to_blp(image, self.make_mipmaps, self.target, self.mipmap_options)
+            .map_err(convert_error)?;
+        let bytes = encode_blp(&blp).map_err(encode_error)?;
+        self.writer.write_all(&bytes)?;
+        Ok(())
+    }
+}
+
+fn make_image(
+    buf: &[u8],
+    width: u32,
+    height: u32,
+    color_type: ColorType,
+) -> ImageResult<DynamicImage> {
+    let image = match color_type {
+        ColorType::L8 => from_raw(width, height, buf.to_vec()).map(DynamicImage::ImageLuma8),
+        ColorType::La8 => from_raw(width, height, buf.to_vec()).map(DynamicImage::ImageLumaA8),
+        ColorType::Rgb8 => from_raw(width, height, buf.to_vec()).map(DynamicImage::ImageRgb8),
+        ColorType::Rgba8 => from_raw(width, height, buf.to_vec()).map(DynamicImage::ImageRgba8),
+        ColorType::L16 => from_raw(width, height, native_u16(buf)).map(DynamicImage::ImageLuma16),
+        ColorType::La16 => from_raw(width, height, native_u16(buf)).map(DynamicImage::ImageLumaA16),
+        ColorType::Rgb16 => from_raw(width, height, native_u16(buf)).map(DynamicImage::ImageRgb16),
+        ColorType::Rgba16 => {
+            from_raw(width, height, native_u16(buf)).map(DynamicImage::ImageRgba16)
+        }
+        ColorType::Rgb32F => {
+            from_raw(width, height, native_f32(buf)).map(DynamicImage::ImageRgb32F)
+        }
+        ColorType::Rgba32F => {
+            from_raw(width, height, native_f32(buf)).map(DynamicImage::ImageRgba32F)
+        }
+        _ => {
+            return Err(ImageError::Unsupported(
+                UnsupportedError::from_format_and_kind(
+                    blp_hint(),
+                    UnsupportedErrorKind::Color(color_type.into()),
+                ),
+            ))
+        }
+    };
+    image.ok_or_else(|| {
+        ImageError::Parameter(ParameterError::from_kind(
+            ParameterErrorKind::DimensionMismatch,
+        ))
+    })
+}
+
+fn from_raw<P: Pixel>(
+    width: u32,
+    height: u32,
+    buf: Vec<P::Subpixel>,
+) -> Option<ImageBuffer<P, Vec<P::Subpixel>>> {
+    ImageBuffer::from_raw(width, height, buf)
+}
+
+fn native_u16(buf: &[u8]) -> Vec<u16> {
+    buf.chunks_exact(2)
+        .map(|c| u16::from_ne_bytes([c[0], c[1]]))
+        .collect()
+}
+
+fn native_f32(buf: &[u8]) -> Vec<f32> {
+    buf.chunks_exact(4)
+        .map(|c| f32::from_ne_bytes([c[0], c[1], c[2], c[3]]))
+        .collect()
+}
+
+fn blp_hint() -> ImageFormatHint {
+    ImageFormatHint::Name("BLP".into())
+}
+
+fn convert_error(err: ConvertError) -> ImageError {
+    match err {
+        ConvertError::Convert(e) => e,
+        _ => ImageError::Encoding(EncodingError::new(blp_hint(), err)),
+    }
+}
+
+fn encode_error(err: Error) -> ImageError {
+    ImageError::Encoding(EncodingError::new(blp_hint(), err))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::convert::{AlphaBits, Blp2Format, BlpOldFormat};
+    use crate::parser::parse_blp;
+    use crate::test_util;
+
+    fn test_image() -> DynamicImage {
+        let image = DynamicImage::ImageRgba8(test_util::test_image(32, 32, 255));
+        DynamicImage::ImageRgb8(image.into_rgb8())
+    }
+
+    #[test]
+    fn test_encoder_matches_convert() {
+        let image = test_image();
+        let target = BlpTarget::Blp2(Blp2Format::Raw3);
+        let mut output = vec![];
+        BlpEncoder::new(&mut output)
+            .with_target(target.clone())
+            .with_mipmap_filter(FilterType::Nearest)
+            .write_image(image.as_bytes(), 32, 32, image.color())
+            .expect("encoded");
+
+        let blp = image_to_blp(image, true, target, FilterType::Nearest).expect("converted");
+        assert_eq!(output, encode_blp(&blp).expect("encoded"));
+        let (_, parsed) = parse_blp(&output).expect("parsed");
+        assert_eq!(parsed, blp);
+    }
+
+    #[test]
+    fn test_encoder_blp0_unsupported() {
+        let image = test_image();
+        let target = BlpTarget::Blp0(BlpOldFormat::Raw1 {
+            alpha_bits: AlphaBits::NoAlpha,
+            options: Default::default(),
+        });
+        let mut output = vec![];
+        let res = BlpEncoder::new(&mut output)
+            .with_target(target)
+            .write_image(image.as_bytes(), 32, 32, image.color());
+        assert!(matches!(res, Err(ImageError::Unsupported(_))));
+        assert!(output.is_empty());
+    }
+
+    #[test]
+    fn test_encoder_wrong_buffer() {
+        let mut output = vec![];
+        let res = BlpEncoder::new(&mut output).write_image(&[0; 10], 32, 32, ColorType::Rgba8);
+        assert!(matches!(res, Err(ImageError::Parameter(_))));
+    }
+}
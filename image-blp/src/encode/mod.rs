@@ -1,8 +1,10 @@
+mod encoder;
 pub mod error;
 mod primitives;
 
 use super::types::*;
 use crate::path::make_mipmap_path;
+pub use encoder::BlpEncoder;
 use error::Error;
 use log::*;
 use primitives::push_le_u32;
@@ -31,6 +31,10 @@
 //! ```
 //! See example `examples/load.rs` for full code.
 //!
+//! If the file is already in memory (e.g. memory mapped archive), [parser::parse_blp_ref]
+//! parses it without copying of mipmaps into [types::BlpImageRef] that can be passed
+//! to [convert::blp_to_image] as well.
+//!
 //! # Example: saving
 //!
 //! The crate provides simplified API for specifing which type of BLP do you want to use.
@@ -53,6 +57,7 @@
 //!     make_mipmaps,
 //!     BlpTarget::Blp1(BlpOldFormat::Raw1 {
 //!         alpha_bits: AlphaBits::Bit1,
+//!         options: Default::default(),
 //!     }),
 //!     FilterType::Nearest,
 //! )
@@ -61,6 +66,22 @@
 //! ```  
 //! See example `examples/save.rs` for full code.
 //!
+//! # Example: image crate decoder
+//!
+//! BLP files can be also loaded with generic code of [image] crate via
+//! [parser::BlpDecoder] that implements `ImageDecoder` trait:
+//! ```no_run
+//! # use ::image::DynamicImage;
+//! # use image_blp::parser::BlpDecoder;
+//! # let blp_filename = "test.blp";
+//! let file = std::io::BufReader::new(std::fs::File::open(blp_filename).expect("opened"));
+//! let decoder = BlpDecoder::new(file).expect("header").with_mipmap_level(0);
+//! let image = DynamicImage::from_decoder(decoder).expect("decoded");
+//! ```
+//!
+//! The same way [encode::BlpEncoder] implements `ImageEncoder` trait and
+//! can be used to write any image as BLP file.
+//!
 //! # CLI tool
 //!
 //! The library is used to build universal CLI tool [blp-conv] that allows
@@ -107,7 +128,12 @@ pub mod encode;
 pub mod parser;
 /// Utilities for mipmaps filename generation
 pub mod path;
+#[cfg(test)]
+mod test_util;
 /// Defines structure of parsed BLP file
 pub mod types;
+/// Validation of BLP files against the format specification
+pub mod validation;
 
 pub use types::*;
+pub use validation::validate;
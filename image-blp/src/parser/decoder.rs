@@ -0,0 +1,201 @@
+use super::error::LoadError;
+use super::reader::BlpReader;
+use crate::convert::{blp_to_image, Error as ConvertError};
+use crate::types::*;
+use ::image::{
+    error::{DecodingError, ImageFormatHint, ParameterError, ParameterErrorKind},
+    ColorType, DynamicImage, ImageDecoder, ImageDecoderRect, ImageError, ImageResult, Progress,
+};
+use std::io::{Cursor, Read, Seek};
+
+/// Implementation of [ImageDecoder] for BLP files. That allows to
+/// load BLP files with generic code of [image](https://crates.io/crates/image)
+/// crate, for instance, via [DynamicImage::from_decoder].
+///
+/// Dimensions and color type are derived from the [BlpHeader] without
+/// decoding of pixels. Only the selected mipmap level is read from the
+/// stream. The decoder doesn't have access to external mipmaps, so BLP0
+/// files should be loaded with [load_blp](super::load_blp) instead.
+///
+/// ```no_run
+/// # use ::image::DynamicImage;
+/// # use image_blp::parser::BlpDecoder;
+/// let file = std::fs::File::open("test.blp").expect("opened");
+/// let decoder = BlpDecoder::new(std::io::BufReader::new(file))
+///     .expect("header")
+///     .with_mipmap_level(1);
+/// let image = DynamicImage::from_decoder(decoder).expect("decoded");
+/// ```
+pub struct BlpDecoder<R> {
+    reader: BlpReader<R>,
+    mipmap_level: usize,
+}
+
+impl<R: Read + Seek> BlpDecoder<R> {
+    /// Read header of BLP file from the stream. The stream is expected
+    /// to be positioned at the start of the file.
+    pub fn new(reader: R) -> Result<Self, LoadError> {
+        Ok(BlpDecoder {
+            reader: BlpReader::new(reader)?,
+            mipmap_level: 0,
+        })
+    }
+
+    /// Select which mipmap level to decode. 0 level means original image.
+    pub fn with_mipmap_level(mut self, mipmap_level: usize) -> Self {
+        self.mipmap_level = mipmap_level;
+        self
+    }
+
+    /// Get parsed header of the BLP file
+    pub fn header(&self) -> &BlpHeader {
+        self.reader.header()
+    }
+
+    /// Get selected mipmap level
+    pub fn mipmap_level(&self) -> usize {
+        self.mipmap_level
+    }
+
+    fn decode(&mut self) -> ImageResult<DynamicImage> {
+        let color_type = self.color_type();
+        let blp = self
+            .reader
+            .read_mipmap(self.mipmap_level)
+            .map_err(decoding_error)?;
+        let image = blp_to_image(&blp, self.mipmap_level).map_err(convert_error)?;
+
+        Ok(match color_type {
+            ColorType::Rgb8 => DynamicImage::ImageRgb8(image.into_rgb8()),
+            _ => DynamicImage::ImageRgba8(image.into_rgba8()),
+        })
+    }
+}
+
+impl<'a, R: 'a + Read + Seek> ImageDecoder<'a> for BlpDecoder<R> {
+    type Reader = Cursor<Vec<u8>>;
+
+    fn dimensions(&self) -> (u32, u32) {
+        self.header().mipmap_size(self.mipmap_level)
+    }
+
+    fn color_type(&self) -> ColorType {
+        let header = self.header();
+        let has_palette = match header.flags {
+            BlpFlags::Old { .. } => header.content == BlpContentTag::Direct,
+            BlpFlags::Blp2 { compression, .. } => compression == Compression::Raw1,
+        };
+        if has_palette && header.alpha_bits() == 0 {
+            ColorType::Rgb8
+        } else {
+            ColorType::Rgba8
+        }
+    }
+
+    fn into_reader(mut self) -> ImageResult<Self::Reader> {
+        Ok(Cursor::new(self.decode()?.into_bytes()))
+    }
+
+    fn read_image(mut self, buf: &mut [u8]) -> ImageResult<()> {
+        assert_eq!(u64::try_from(buf.len()), Ok(self.total_bytes()));
+        buf.copy_from_slice(self.decode()?.as_bytes());
+        Ok(())
+    }
+}
+
+impl<'a, R: 'a + Read + Seek> ImageDecoderRect<'a> for BlpDecoder<R> {
+    fn read_rect_with_progress<F: Fn(Progress)>(
+        &mut self,
+        x: u32,
+        y: u32,
+        width: u32,
+        height: u32,
+        buf: &mut [u8],
+        _progress_callback: F,
+    ) -> ImageResult<()> {
+        // Note: progress is not reported as the whole mipmap level is decoded at once.
+        let (full_width, full_height) = self.dimensions();
+        if x.saturating_add(width) > full_width || y.saturating_add(height) > full_height {
+            return Err(ImageError::Parameter(ParameterError::from_kind(
+                ParameterErrorKind::DimensionMismatch,
+            )));
+        }
+        let pixel_bytes = self.color_type().bytes_per_pixel() as usize;
+        let row_bytes = width as usize * pixel_bytes;
+        assert_eq!(buf.len(), row_bytes * height as usize);
+
+        let image = self.decode()?;
+
+        let full_row_bytes = full_width as usize * pixel_bytes;
+        let bytes = image.as_bytes();
+        for (i, row) in buf.chunks_exact_mut(row_bytes).enumerate() {
+            let from = (y as usize + i) * full_row_bytes + x as usize * pixel_bytes;
+            row.copy_from_slice(&bytes[from..from + row_bytes]);
+        }
+        Ok(())
+    }
+}
+
+fn decoding_error(err: LoadError) -> ImageError {
+    match err {
+        LoadError::Stream(e) => ImageError::IoError(e),
+        _ => ImageError::Decoding(DecodingError::new(ImageFormatHint::Name("BLP".into()), err)),
+    }
+}
+
+fn convert_error(err: ConvertError) -> ImageError {
+    match err {
+        ConvertError::Convert(e) => e,
+        _ => ImageError::Decoding(DecodingError::new(ImageFormatHint::Name("BLP".into()), err)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::convert::{Blp2Format, BlpTarget};
+    use crate::encode::encode_blp;
+    use crate::test_util::{image_blp, test_image};
+    use test_log::test;
+
+    #[test]
+    fn test_decoder_matches_convert() {
+        let target = BlpTarget::Blp2(Blp2Format::Raw3);
+        let blp = image_blp(test_image(16, 16, 128), true, target);
+        let bytes = encode_blp(&blp).expect("encoded");
+
+        for level in 0..blp.image_count() {
+            let decoder = BlpDecoder::new(Cursor::new(&bytes))
+                .expect("header")
+                .with_mipmap_level(level);
+            assert_eq!(decoder.dimensions(), blp.header.mipmap_size(level));
+            assert_eq!(decoder.color_type(), ColorType::Rgba8);
+            let decoded = DynamicImage::from_decoder(decoder).expect("decoded");
+            let expected = blp_to_image(&blp, level).expect("converted");
+            assert_eq!(decoded, expected);
+        }
+    }
+
+    #[test]
+    fn test_decoder_rect() {
+        let target = BlpTarget::Blp2(Blp2Format::Raw3);
+        let blp = image_blp(test_image(16, 16, 128), false, target);
+        let bytes = encode_blp(&blp).expect("encoded");
+        let expected = blp_to_image(&blp, 0).expect("converted").into_rgba8();
+
+        let mut decoder = BlpDecoder::new(Cursor::new(&bytes)).expect("header");
+        let mut buf = vec![0; 4 * 3 * 4];
+        decoder.read_rect(5, 2, 4, 3, &mut buf).expect("rect");
+        for (i, pixel) in buf.chunks_exact(4).enumerate() {
+            let x = 5 + (i as u32) % 4;
+            let y = 2 + (i as u32) / 4;
+            assert_eq!(pixel, expected.get_pixel(x, y).0);
+        }
+    }
+
+    #[test]
+    fn test_decoder_wrong_magic() {
+        let bytes = vec![0; 200];
+        assert!(BlpDecoder::new(Cursor::new(bytes)).is_err());
+    }
+}
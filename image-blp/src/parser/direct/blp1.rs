@@ -2,14 +2,14 @@ use super::super::error::Error;
 use super::super::types::Parser;
 use crate::types::*;
 use log::*;
-use nom::{multi::count, number::complete::le_u8, Err};
+use nom::{bytes::complete::take, Err};
 
 pub fn parse_raw1<'a>(
     blp_header: &BlpHeader,
     original_input: &'a [u8],
     offsets: &[u32],
     sizes: &[u32],
-    images: &mut Vec<Raw1Image>,
+    images: &mut Vec<Raw1ImageRef<'a>>,
     input: &'a [u8],
 ) -> Parser<'a, ()> {
     let mut read_image = |i: usize| {
@@ -22,7 +22,7 @@ pub fn parse_raw1<'a>(
                 offset,
                 original_input.len()
             );
-            return Err(Err::Failure(Error::<&[u8]>::OutOfBounds(0)));
+            return Err(Err::Failure(Error::<&[u8]>::OutOfBounds(i)));
         }
         if (offset + size) as usize > original_input.len() {
             error!(
@@ -31,19 +31,13 @@ pub fn parse_raw1<'a>(
                 offset + size,
                 original_input.len()
             );
-            return Err(Err::Failure(Error::OutOfBounds(0)));
+            return Err(Err::Failure(Error::OutOfBounds(i)));
         }
 
         let image_bytes = &original_input[offset as usize..(offset + size) as usize];
-        let n = blp_header.mipmap_pixels(i);
-        let (input, indexed_rgb) = count(le_u8, n as usize)(image_bytes)?;
-        let an = (n * blp_header.alpha_bits() + 7) / 8;
-        let (_, indexed_alpha) = count(le_u8, an as usize)(input)?;
-
-        images.push(Raw1Image {
-            indexed_rgb,
-            indexed_alpha,
-        });
+        let (_, image) =
+            parse_raw1_image(blp_header, i, image_bytes).map_err(|e| e.map(|e| e.in_mipmap(i)))?;
+        images.push(image);
         Ok(())
     };
 
@@ -56,3 +50,23 @@ pub fn parse_raw1<'a>(
     }
     Ok((input, ()))
 }
+
+/// Parse single RAW1 mipmap level from bytes of the level
+pub fn parse_raw1_image<'a>(
+    blp_header: &BlpHeader,
+    mipmap_number: usize,
+    input: &'a [u8],
+) -> Parser<'a, Raw1ImageRef<'a>> {
+    let n = blp_header.mipmap_pixels(mipmap_number);
+    let (input, indexed_rgb) = take(n as usize)(input)?;
+    let an = (n * blp_header.alpha_bits()).div_ceil(8);
+    let (input, indexed_alpha) = take(an as usize)(input)?;
+
+    Ok((
+        input,
+        Raw1ImageRef {
+            indexed_rgb,
+            indexed_alpha,
+        },
+    ))
+}
@@ -2,19 +2,14 @@ use super::super::error::Error;
 use super::super::types::Parser;
 use crate::types::*;
 use log::*;
-use nom::{
-    error::context,
-    multi::count,
-    number::complete::{le_u32, le_u8},
-    Err,
-};
+use nom::{bytes::complete::take, error::context, Err};
 
 pub fn parse_raw3<'a>(
     blp_header: &BlpHeader,
     original_input: &'a [u8],
     offsets: &[u32],
     sizes: &[u32],
-    images: &mut Vec<Raw3Image>,
+    images: &mut Vec<Raw3ImageRef<'a>>,
     input: &'a [u8],
 ) -> Parser<'a, ()> {
     let mut read_image = |i: usize| {
@@ -27,7 +22,7 @@ pub fn parse_raw3<'a>(
                 offset,
                 original_input.len()
             );
-            return Err(Err::Failure(Error::<&[u8]>::OutOfBounds(0)));
+            return Err(Err::Failure(Error::<&[u8]>::OutOfBounds(i)));
         }
         if (offset + size) as usize > original_input.len() {
             error!(
@@ -36,21 +31,15 @@ pub fn parse_raw3<'a>(
                 offset + size,
                 original_input.len()
             );
-            return Err(Err::Failure(Error::OutOfBounds(0)));
+            return Err(Err::Failure(Error::OutOfBounds(i)));
         }
 
         trace!("Expecting size of image: {}", size);
         let image_bytes = &original_input[offset as usize..(offset + size) as usize];
         trace!("We have {} bytes", image_bytes.len());
-        let n = blp_header.mipmap_pixels(i);
-        trace!(
-            "For mipmap size {:?} we should fetch {} bytes",
-            blp_header.mipmap_size(i),
-            n * 4
-        );
-        let (_, pixels) = count(le_u32, n as usize)(image_bytes)?;
-
-        images.push(Raw3Image { pixels });
+        let (_, image) =
+            parse_raw3_image(blp_header, i, image_bytes).map_err(|e| e.map(|e| e.in_mipmap(i)))?;
+        images.push(image);
         Ok(())
     };
 
@@ -79,7 +68,7 @@ pub fn parse_dxtn<'a>(
     original_input: &'a [u8],
     offsets: &[u32],
     sizes: &[u32],
-    images: &mut Vec<DxtnImage>,
+    images: &mut Vec<DxtnImageRef<'a>>,
     input: &'a [u8],
 ) -> Parser<'a, ()> {
     trace!("{:?}", blp_header);
@@ -94,7 +83,7 @@ pub fn parse_dxtn<'a>(
                 offset,
                 original_input.len()
             );
-            return Err(Err::Failure(Error::<&[u8]>::OutOfBounds(0)));
+            return Err(Err::Failure(Error::<&[u8]>::OutOfBounds(i)));
         }
         if (offset + size) as usize > original_input.len() {
             error!(
@@ -103,28 +92,13 @@ pub fn parse_dxtn<'a>(
                 offset + size,
                 original_input.len()
             );
-            return Err(Err::Failure(Error::OutOfBounds(0)));
+            return Err(Err::Failure(Error::OutOfBounds(i)));
         }
 
         let image_bytes = &original_input[offset as usize..(offset + size) as usize];
-        let n = blp_header.mipmap_pixels(i);
-        let blocks_n = ((n as f32) / 16.0).ceil() as usize;
-        let mut blocks_size = blocks_n * dxtn.block_size();
-        trace!("Dxtn blocks count: {blocks_n}");
-        trace!("Dxtn format: {dxtn:?}, block size: {}", dxtn.block_size());
-        trace!(
-            "Left size: {}, expected size: {}",
-            image_bytes.len(),
-            blocks_size
-        );
-        if blocks_size > image_bytes.len() {
-            warn!("Data is smaller than expected! Trying to read only whole number of blocks");
-            let new_blocks_n = image_bytes.len() / dxtn.block_size();
-            warn!("Reading {new_blocks_n} blocks");
-            blocks_size = new_blocks_n * dxtn.block_size();
-        }
-        let (_, content) = context("dxtn blocks", count(le_u8, blocks_size))(image_bytes)?;
-        images.push(DxtnImage { content });
+        let (_, image) = parse_dxtn_image(blp_header, dxtn, i, image_bytes)
+            .map_err(|e| e.map(|e| e.in_mipmap(i)))?;
+        images.push(image);
         Ok(())
     };
 
@@ -137,3 +111,42 @@ pub fn parse_dxtn<'a>(
     }
     Ok((input, ()))
 }
+
+/// Parse single RAW3 mipmap level from bytes of the level
+pub fn parse_raw3_image<'a>(
+    blp_header: &BlpHeader,
+    mipmap_number: usize,
+    input: &'a [u8],
+) -> Parser<'a, Raw3ImageRef<'a>> {
+    let n = blp_header.mipmap_pixels(mipmap_number);
+    trace!(
+        "For mipmap size {:?} we should fetch {} bytes",
+        blp_header.mipmap_size(mipmap_number),
+        n * 4
+    );
+    let (input, pixels) = take(n as usize * 4)(input)?;
+    Ok((input, Raw3ImageRef { pixels }))
+}
+
+/// Parse single DXTn mipmap level from bytes of the level
+pub fn parse_dxtn_image<'a>(
+    blp_header: &BlpHeader,
+    dxtn: DxtnFormat,
+    mipmap_number: usize,
+    input: &'a [u8],
+) -> Parser<'a, DxtnImageRef<'a>> {
+    let n = blp_header.mipmap_pixels(mipmap_number);
+    let blocks_n = ((n as f32) / 16.0).ceil() as usize;
+    let mut blocks_size = blocks_n * dxtn.block_size();
+    trace!("Dxtn blocks count: {blocks_n}");
+    trace!("Dxtn format: {dxtn:?}, block size: {}", dxtn.block_size());
+    trace!("Left size: {}, expected size: {}", input.len(), blocks_size);
+    if blocks_size > input.len() {
+        warn!("Data is smaller than expected! Trying to read only whole number of blocks");
+        let new_blocks_n = input.len() / dxtn.block_size();
+        warn!("Reading {new_blocks_n} blocks");
+        blocks_size = new_blocks_n * dxtn.block_size();
+    }
+    let (input, content) = context("dxtn blocks", take(blocks_size))(input)?;
+    Ok((input, DxtnImageRef { content }))
+}
@@ -7,7 +7,9 @@ use super::types::Parser;
 use crate::types::*;
 use blp0::parse_blp0;
 use blp1::parse_raw1;
+pub use blp1::parse_raw1_image;
 use blp2::{parse_dxtn, parse_raw3};
+pub use blp2::{parse_dxtn_image, parse_raw3_image};
 use log::*;
 use nom::{error::context, multi::count, number::complete::le_u32, Err};
 
@@ -16,7 +18,7 @@ pub fn parse_direct_content<'a, F>(
     external_mipmaps: F,
     original_input: &'a [u8],
     input: &'a [u8],
-) -> Parser<'a, BlpContent>
+) -> Parser<'a, BlpContentRef<'a>>
 where
     F: FnMut(usize) -> Result<Option<&'a [u8]>, Box<dyn std::error::Error>>,
 {
@@ -25,6 +27,7 @@ where
     match blp_header.flags {
         BlpFlags::Blp2 {
             compression,
+            alpha_bits,
             alpha_type,
             ..
         } => match blp_header.mipmap_locator {
@@ -50,7 +53,7 @@ where
                             input,
                         )
                     })(input)?;
-                    Ok((input, BlpContent::Raw1(BlpRaw1 { cmap, images })))
+                    Ok((input, BlpContentRef::Raw1(BlpRaw1Ref { cmap, images })))
                 }
                 Compression::Raw3 => {
                     let mut images = vec![];
@@ -65,59 +68,15 @@ where
                             input,
                         )
                     })(input)?;
-                    Ok((input, BlpContent::Raw3(BlpRaw3 { cmap, images })))
+                    Ok((input, BlpContentRef::Raw3(BlpRaw3Ref { cmap, images })))
                 }
-                Compression::Dxtc if alpha_type == 0 => {
-                    let format = DxtnFormat::Dxt1;
-                    let mut images = vec![];
-                    let (input, _) = context("dxt1 format", |input| {
-                        parse_dxtn(
-                            blp_header,
-                            format,
-                            original_input,
-                            &offsets,
-                            &sizes,
-                            &mut images,
-                            input,
-                        )
-                    })(input)?;
-                    Ok((
-                        input,
-                        BlpContent::Dxt1(BlpDxtn {
-                            format,
-                            cmap,
-                            images,
-                        }),
-                    ))
-                }
-                Compression::Dxtc if alpha_type == 1 => {
-                    let format = DxtnFormat::Dxt3;
-                    let mut images = vec![];
-                    let (input, _) = context("dxt3 format", |input| {
-                        parse_dxtn(
-                            blp_header,
-                            format,
-                            original_input,
-                            &offsets,
-                            &sizes,
-                            &mut images,
-                            input,
-                        )
-                    })(input)?;
-
-                    Ok((
-                        input,
-                        BlpContent::Dxt3(BlpDxtn {
-                            format,
-                            cmap,
-                            images,
-                        }),
-                    ))
-                }
-                Compression::Dxtc if alpha_type == 7 => {
-                    let format = DxtnFormat::Dxt5;
+                Compression::Dxtc => {
+                    let format = dxtn_format(alpha_type, alpha_bits).ok_or_else(|| {
+                        error!("Alpha type {} is not supported for BLP2!", alpha_type);
+                        Err::Failure(Error::<&[u8]>::Blp2UnknownAlphaType(alpha_type))
+                    })?;
                     let mut images = vec![];
-                    let (input, _) = context("dxt5 format", |input| {
+                    let (input, _) = context("dxtn format", |input| {
                         parse_dxtn(
                             blp_header,
                             format,
@@ -128,21 +87,20 @@ where
                             input,
                         )
                     })(input)?;
+                    let content = BlpDxtnRef {
+                        format,
+                        cmap,
+                        images,
+                    };
                     Ok((
                         input,
-                        BlpContent::Dxt5(BlpDxtn {
-                            format,
-                            cmap,
-                            images,
-                        }),
+                        match format {
+                            DxtnFormat::Dxt1 => BlpContentRef::Dxt1(content),
+                            DxtnFormat::Dxt3 => BlpContentRef::Dxt3(content),
+                            DxtnFormat::Dxt5 => BlpContentRef::Dxt5(content),
+                        },
                     ))
                 }
-                Compression::Dxtc => {
-                    error!("Alpha type {} is not supported for BLP2!", alpha_type);
-                    Err(Err::Failure(Error::<&[u8]>::Blp2UnknownAlphaType(
-                        alpha_type,
-                    )))
-                }
             },
         },
         BlpFlags::Old { .. } => {
@@ -160,7 +118,21 @@ where
                     input,
                 )?,
             };
-            Ok((input, BlpContent::Raw1(BlpRaw1 { cmap, images })))
+            Ok((input, BlpContentRef::Raw1(BlpRaw1Ref { cmap, images })))
         }
     }
 }
+
+/// Get DXTn compression type from BLP2 `alpha_type` field. Type 8 doesn't
+/// name the compression, so it is chosen by `alpha_bits` as the client does.
+pub fn dxtn_format(alpha_type: u8, alpha_bits: u8) -> Option<DxtnFormat> {
+    match (alpha_type, alpha_bits) {
+        (0, _) => Some(DxtnFormat::Dxt1),
+        (1, _) => Some(DxtnFormat::Dxt3),
+        (7, _) => Some(DxtnFormat::Dxt5),
+        (8, 0 | 1) => Some(DxtnFormat::Dxt1),
+        (8, 4) => Some(DxtnFormat::Dxt3),
+        (8, 8) => Some(DxtnFormat::Dxt5),
+        _ => None,
+    }
+}
@@ -1,4 +1,4 @@
-use nom::error::{ContextError, ErrorKind, ParseError};
+use nom::error::{ContextError, ErrorKind, ParseError as NomParseError};
 use std::fmt;
 use thiserror::Error;
 
@@ -6,13 +6,194 @@ use thiserror::Error;
 #[derive(Debug, Error)]
 pub enum LoadError {
     #[error("{0}")]
-    Parsing(String),
+    Parsing(ParseError),
     #[error("File system error with file {0}, due: {1}")]
     FileSystem(std::path::PathBuf, std::io::Error),
     #[error("Input stream is incomplete, needed: {0:?}")]
     Incomplete(nom::Needed),
     #[error("Cannot derive mipmap name for {0}")]
     InvalidFilename(std::path::PathBuf),
+    #[error("Failed to read BLP from stream, due: {0}")]
+    Stream(std::io::Error),
+}
+
+impl LoadError {
+    /// Convert parser error and calculate offsets relative to `input`, that
+    /// is located at `base_offset` in the file.
+    pub fn parsing(err: nom::Err<Error<&[u8]>>, input: &[u8], base_offset: usize) -> Self {
+        match err {
+            nom::Err::Incomplete(needed) => LoadError::Incomplete(needed),
+            nom::Err::Error(e) | nom::Err::Failure(e) => {
+                LoadError::Parsing(ParseError::new(e, input, base_offset))
+            }
+        }
+    }
+
+    /// Get structured parse error if the loading failed due malformed content
+    pub fn parse_error(&self) -> Option<&ParseError> {
+        match self {
+            LoadError::Parsing(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+/// Conversion without knowledge of input, so [ParseError::offset] is not filled.
+impl<'a> From<nom::Err<Error<&'a [u8]>>> for LoadError {
+    fn from(err: nom::Err<Error<&'a [u8]>>) -> Self {
+        LoadError::parsing(err, &[], 0)
+    }
+}
+
+/// Parser [Error] that is detached from the input, so it can be matched
+/// by callers and sent between threads.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    /// What actually went wrong
+    pub kind: ParseErrorKind,
+    /// Stack of parser contexts from outermost to innermost
+    pub context: Vec<String>,
+    /// Byte offset in the file where the parser failed, if known
+    pub offset: Option<usize>,
+    /// Mipmap level which was parsed when the error occured, if any
+    pub mipmap: Option<usize>,
+}
+
+impl ParseError {
+    /// Flatten parser error, offsets are calculated relative to `input`, that
+    /// is located at `base_offset` in the file.
+    pub fn new(err: Error<&[u8]>, input: &[u8], base_offset: usize) -> Self {
+        let mut res = ParseError {
+            kind: ParseErrorKind::Nom(ErrorKind::Fail),
+            context: vec![],
+            offset: None,
+            mipmap: None,
+        };
+        let mut err = err;
+        loop {
+            err = match err {
+                Error::Context(ctx, inner) => {
+                    res.context.push(ctx);
+                    *inner
+                }
+                Error::Mipmap(i, inner) => {
+                    res.mipmap = Some(i);
+                    *inner
+                }
+                Error::WrongMagic(v) => {
+                    res.offset = Some(base_offset);
+                    res.kind = ParseErrorKind::WrongMagic(v);
+                    break;
+                }
+                Error::ExternalMipmap(i, e) => {
+                    res.mipmap = Some(i);
+                    res.kind = ParseErrorKind::ExternalMipmap(i, e.to_string());
+                    break;
+                }
+                Error::MissingImage(i) => {
+                    res.mipmap = Some(i);
+                    res.kind = ParseErrorKind::MissingImage(i);
+                    break;
+                }
+                Error::OutOfBounds(i) => {
+                    res.mipmap = Some(i);
+                    res.kind = ParseErrorKind::OutOfBounds(i);
+                    break;
+                }
+                Error::Blp2NoExternalMips => {
+                    res.kind = ParseErrorKind::Blp2NoExternalMips;
+                    break;
+                }
+                Error::Blp2UnknownCompression(v) => {
+                    res.kind = ParseErrorKind::Blp2UnknownCompression(v);
+                    break;
+                }
+                Error::Blp2UnknownAlphaType(v) => {
+                    res.kind = ParseErrorKind::Blp2UnknownAlphaType(v);
+                    break;
+                }
+                Error::Blp2UnexpectedJpegCompression => {
+                    res.kind = ParseErrorKind::Blp2UnexpectedJpegCompression;
+                    break;
+                }
+                Error::Nom(rest, kind) => {
+                    let start = input.as_ptr() as usize;
+                    let pos = rest.as_ptr() as usize;
+                    if !input.is_empty() && pos >= start && pos + rest.len() <= start + input.len()
+                    {
+                        res.offset = Some(base_offset + pos - start);
+                    }
+                    res.kind = ParseErrorKind::Nom(kind);
+                    break;
+                }
+            }
+        }
+        res
+    }
+
+    /// The input is not a BLP file at all
+    pub fn is_wrong_magic(&self) -> bool {
+        matches!(self.kind, ParseErrorKind::WrongMagic(_))
+    }
+
+    /// The file ends before the content declared in the header
+    pub fn is_truncated(&self) -> bool {
+        matches!(
+            self.kind,
+            ParseErrorKind::OutOfBounds(_) | ParseErrorKind::Nom(ErrorKind::Eof)
+        )
+    }
+
+    /// The file is valid, but uses features that the library doesn't support
+    pub fn is_unsupported(&self) -> bool {
+        matches!(
+            self.kind,
+            ParseErrorKind::Blp2UnknownCompression(_)
+                | ParseErrorKind::Blp2UnknownAlphaType(_)
+                | ParseErrorKind::Blp2NoExternalMips
+        )
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.kind)?;
+        if let Some(mipmap) = self.mipmap {
+            write!(f, ", mipmap {}", mipmap)?;
+        }
+        if let Some(offset) = self.offset {
+            write!(f, ", offset {}", offset)?;
+        }
+        if !self.context.is_empty() {
+            write!(f, ", context: {}", self.context.join(" > "))?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// Leaf of parser [Error] without references to the input
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum ParseErrorKind {
+    #[error("Unexpected magic value {0}. The file format is not BLP or not supported.")]
+    WrongMagic(String),
+    #[error("Failed to extract external mipmap number {0} with error {1}")]
+    ExternalMipmap(usize, String),
+    #[error("There is no body of image for BLP0 mipmap number {0}")]
+    MissingImage(usize),
+    #[error("Part of image exceeds bounds of file for mipmap number {0}")]
+    OutOfBounds(usize),
+    #[error("BLP2 doesn't support external mipmaps")]
+    Blp2NoExternalMips,
+    #[error("Library doesn't support compression tag: {0}")]
+    Blp2UnknownCompression(u8),
+    #[error("Library doesn't support alpha type: {0}")]
+    Blp2UnknownAlphaType(u8),
+    #[error("Impossible branch, JPEG compression but direct content type")]
+    Blp2UnexpectedJpegCompression,
+    #[error("Parser error {0:?}")]
+    Nom(ErrorKind),
 }
 
 /// Errors that BLP parser can produce
@@ -38,6 +219,15 @@ pub enum Error<I: fmt::Debug> {
     Nom(I, ErrorKind),
     #[error("Context: {0}. Error: {1}")]
     Context(String, Box<Self>),
+    #[error("Mipmap {0}. Error: {1}")]
+    Mipmap(usize, Box<Self>),
+}
+
+impl<I: fmt::Debug> Error<I> {
+    /// Mark that the error occured while parsing the given mipmap level
+    pub fn in_mipmap(self, i: usize) -> Self {
+        Error::Mipmap(i, Box::new(self))
+    }
 }
 
 impl<'a> From<(&'a [u8], ErrorKind)> for Error<&'a [u8]> {
@@ -46,7 +236,7 @@ impl<'a> From<(&'a [u8], ErrorKind)> for Error<&'a [u8]> {
     }
 }
 
-impl<'a> ParseError<&'a [u8]> for Error<&'a [u8]> {
+impl<'a> NomParseError<&'a [u8]> for Error<&'a [u8]> {
     fn from_error_kind(input: &'a [u8], kind: ErrorKind) -> Self {
         Error::Nom(input, kind)
     }
@@ -61,3 +251,71 @@ impl<'a> ContextError<&'a [u8]> for Error<&'a [u8]> {
         Error::Context(ctx.to_owned(), Box::new(other))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::convert::{Blp2Format, BlpTarget};
+    use crate::encode::encode_blp;
+    use crate::parser::load_blp_from_buf;
+    use crate::test_util::{image_blp, test_image};
+    use crate::types::*;
+    use test_log::test;
+
+    fn test_bytes() -> (BlpHeader, Vec<u8>) {
+        let target = BlpTarget::Blp2(Blp2Format::Raw3);
+        let blp = image_blp(test_image(16, 16, 255), true, target);
+        (blp.header.clone(), encode_blp(&blp).expect("encoded"))
+    }
+
+    fn parse_error(bytes: &[u8]) -> ParseError {
+        match load_blp_from_buf(bytes) {
+            Err(LoadError::Parsing(e)) => e,
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_wrong_magic() {
+        let err = parse_error(b"PNG\0 definetely not a BLP file");
+        assert!(err.is_wrong_magic());
+        assert_eq!(err.offset, Some(0));
+    }
+
+    #[test]
+    fn test_truncated_header() {
+        let (_, bytes) = test_bytes();
+        let err = parse_error(&bytes[..10]);
+        assert!(err.is_truncated());
+        assert_eq!(err.kind, ParseErrorKind::Nom(ErrorKind::Eof));
+        assert_eq!(err.offset, Some(10));
+        assert_eq!(err.context, vec!["header", "alpha_type field"]);
+        assert_eq!(err.mipmap, None);
+    }
+
+    #[test]
+    fn test_truncated_mipmap() {
+        let (header, mut bytes) = test_bytes();
+        let (offsets, _) = header.internal_mipmaps().expect("internal");
+        bytes.truncate(offsets[3] as usize + 1);
+        let err = parse_error(&bytes);
+        assert!(err.is_truncated());
+        assert_eq!(err.kind, ParseErrorKind::OutOfBounds(3));
+        assert_eq!(err.mipmap, Some(3));
+    }
+
+    #[test]
+    fn test_unsupported_compression() {
+        let (_, mut bytes) = test_bytes();
+        bytes[8] = 42;
+        let err = parse_error(&bytes);
+        assert!(err.is_unsupported());
+        assert_eq!(err.kind, ParseErrorKind::Blp2UnknownCompression(42));
+    }
+
+    #[test]
+    fn test_error_is_send() {
+        fn assert_send_sync<T: Send + Sync + 'static>() {}
+        assert_send_sync::<LoadError>();
+    }
+}
@@ -11,6 +11,7 @@ use nom::{
 };
 use std::str;
 
+/// Parse only header of BLP file, color map, JPEG header and mipmaps follow it
 pub fn parse_header(input: &[u8]) -> Parser<BlpHeader> {
     let (input, version) = context("version", parse_magic)(input)?;
     let (input, content_field) = context("content_field field", le_u32)(input)?;
@@ -3,18 +3,14 @@ use super::types::Parser;
 use crate::types::jpeg::MAX_JPEG_HEADER;
 use crate::types::*;
 use log::*;
-use nom::{
-    multi::count,
-    number::complete::{le_u32, le_u8},
-    Err,
-};
+use nom::{bytes::complete::take, number::complete::le_u32, Err};
 
 pub fn parse_jpeg_content<'a, F>(
     blp_header: &BlpHeader,
     mut external_mipmaps: F,
     original_input: &'a [u8],
     input: &'a [u8],
-) -> Parser<'a, BlpJpeg>
+) -> Parser<'a, BlpJpegRef<'a>>
 where
     F: FnMut(usize) -> Result<Option<&'a [u8]>, Box<dyn std::error::Error>>,
 {
@@ -26,7 +22,7 @@ where
         );
     }
     // There is two additional bytes that are not covered by the header size
-    let (input, header) = count(le_u8, (header_size + 2) as usize)(input)?;
+    let (input, header) = take(header_size as usize + 2)(input)?;
     let mut images = vec![];
 
     match blp_header.mipmap_locator {
@@ -34,7 +30,7 @@ where
             let image0_bytes_opt =
                 external_mipmaps(0).map_err(|e| Err::Failure(Error::ExternalMipmap(0, e)))?;
             let image0_bytes = image0_bytes_opt.ok_or(Err::Failure(Error::MissingImage(0)))?;
-            images.push(image0_bytes.to_vec());
+            images.push(image0_bytes);
 
             if blp_header.has_mipmaps() {
                 // funny that there is no hard limit for number of mipmaps
@@ -44,7 +40,7 @@ where
                         .map_err(|e| Err::Failure(Error::ExternalMipmap(i, e)))?;
                     let image_bytes =
                         image_bytes_opt.ok_or(Err::Failure(Error::MissingImage(i)))?;
-                    images.push(image_bytes.to_vec());
+                    images.push(image_bytes);
                 }
             }
         }
@@ -59,7 +55,7 @@ where
                         offset,
                         original_input.len()
                     );
-                    return Err(Err::Failure(Error::<&[u8]>::OutOfBounds(0)));
+                    return Err(Err::Failure(Error::<&[u8]>::OutOfBounds(i)));
                 }
                 if (offset + size) as usize > original_input.len() {
                     error!(
@@ -68,11 +64,11 @@ where
                         offset + size,
                         original_input.len()
                     );
-                    return Err(Err::Failure(Error::OutOfBounds(0)));
+                    return Err(Err::Failure(Error::OutOfBounds(i)));
                 }
 
                 let image_bytes = &original_input[offset as usize..(offset + size) as usize];
-                images.push(image_bytes.to_vec());
+                images.push(image_bytes);
                 Ok(())
             };
 
@@ -85,5 +81,5 @@ where
         }
     }
 
-    Ok((input, BlpJpeg { header, images }))
+    Ok((input, BlpJpegRef { header, images }))
 }
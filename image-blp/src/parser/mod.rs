@@ -1,7 +1,11 @@
+mod decoder;
 mod direct;
 pub mod error;
 mod header;
 mod jpeg;
+mod options;
+mod probe;
+mod reader;
 pub mod types;
 
 #[cfg(test)]
@@ -9,11 +13,18 @@ mod tests;
 
 use super::types::*;
 use crate::path::make_mipmap_path;
+pub use decoder::BlpDecoder;
 use direct::parse_direct_content;
-pub use error::{Error, LoadError};
-use header::parse_header;
+pub use error::{Error, LoadError, ParseError, ParseErrorKind};
+pub(crate) use header::parse_header;
 use jpeg::parse_jpeg_content;
 use nom::error::context;
+pub(crate) use options::LevelFormat;
+pub use options::{
+    load_blp_with_options, parse_blp_with_options, Diagnostic, ParseOptions, ParsedBlp, Strictness,
+};
+pub use probe::{probe_blp, probe_blp_from_reader, BlpInfo};
+pub use reader::{BlpMipmap, BlpMipmapContent, BlpReader};
 use std::path::{Path, PathBuf};
 use types::Parser;
 
@@ -37,33 +48,38 @@ pub fn load_blp_from_buf(buf: &[u8]) -> Result<BlpImage, LoadError> {
 }
 
 fn load_blp_ex<Q>(path: Option<Q>, input: &[u8]) -> Result<BlpImage, LoadError>
+where
+    Q: AsRef<Path>,
+{
+    let mipmaps = match path {
+        Some(path) => preload_mipmaps(path)?,
+        None => vec![],
+    };
+    let (_, image) = parse_blp_with_externals(input, |i| preloaded_mipmaps(&mipmaps, i))
+        .map_err(|e| LoadError::parsing(e, input, 0))?;
+    Ok(image)
+}
+
+/// Read external mipmaps of BLP0 that are located near the root file
+fn preload_mipmaps<Q>(path: Q) -> Result<Vec<Vec<u8>>, LoadError>
 where
     Q: AsRef<Path>,
 {
     // We have to preload all mipmaps in memory as we are constrained with Nom 'a lifetime that
     // should be equal of lifetime of root input stream.
     let mut mipmaps = vec![];
-    if let Some(path) = path.as_ref() {
-        for i in 0..16 {
-            let mipmap_path = make_mipmap_path(path, i)
-                .ok_or_else(|| LoadError::InvalidFilename(path.as_ref().to_owned()))?;
-            if mipmap_path.is_file() {
-                let mipmap = std::fs::read(mipmap_path)
-                    .map_err(|e| LoadError::FileSystem(path.as_ref().to_owned(), e))?;
-                mipmaps.push(mipmap);
-            } else {
-                break;
-            }
+    for i in 0..16 {
+        let mipmap_path = make_mipmap_path(&path, i)
+            .ok_or_else(|| LoadError::InvalidFilename(path.as_ref().to_owned()))?;
+        if mipmap_path.is_file() {
+            let mipmap = std::fs::read(mipmap_path)
+                .map_err(|e| LoadError::FileSystem(path.as_ref().to_owned(), e))?;
+            mipmaps.push(mipmap);
+        } else {
+            break;
         }
     }
-
-    let image = match parse_blp_with_externals(input, |i| preloaded_mipmaps(&mipmaps, i)) {
-        Ok((_, image)) => Ok(image),
-        Err(nom::Err::Incomplete(needed)) => Err(LoadError::Incomplete(needed)),
-        Err(nom::Err::Error(e)) => Err(LoadError::Parsing(format!("{}", e))),
-        Err(nom::Err::Failure(e)) => Err(LoadError::Parsing(format!("{}", e))),
-    }?;
-    Ok(image)
+    Ok(mipmaps)
 }
 
 /// Parse BLP file from slice and fail if we require parse external files (case BLP0)
@@ -71,6 +87,12 @@ pub fn parse_blp(input: &[u8]) -> Parser<BlpImage> {
     parse_blp_with_externals(input, no_mipmaps)
 }
 
+/// Parse BLP file from slice without copying of mipmaps. Fails if we require
+/// parse external files (case BLP0).
+pub fn parse_blp_ref(input: &[u8]) -> Parser<'_, BlpImageRef<'_>> {
+    parse_blp_ref_with_externals(input, no_mipmaps)
+}
+
 /// Helper for `parse_blp` when no external mipmaps are needed
 pub fn no_mipmaps<'a>(_: usize) -> Result<Option<&'a [u8]>, Box<dyn std::error::Error>> {
     Ok(None)
@@ -94,6 +116,20 @@ pub fn parse_blp_with_externals<'a, F>(
     root_input: &'a [u8],
     external_mipmaps: F,
 ) -> Parser<'a, BlpImage>
+where
+    F: FnMut(usize) -> Result<Option<&'a [u8]>, Box<dyn std::error::Error>> + Clone,
+{
+    let (input, image) = parse_blp_ref_with_externals(root_input, external_mipmaps)?;
+    Ok((input, image.to_owned()))
+}
+
+/// Parse BLP file from slice without copying of mipmaps and use user provided
+/// callback to read external mipmaps. The mipmaps borrow both the root input
+/// and buffers returned by the callback.
+pub fn parse_blp_ref_with_externals<'a, F>(
+    root_input: &'a [u8],
+    external_mipmaps: F,
+) -> Parser<'a, BlpImageRef<'a>>
 where
     F: FnMut(usize) -> Result<Option<&'a [u8]>, Box<dyn std::error::Error>> + Clone,
 {
@@ -105,7 +141,7 @@ where
         parse_content(&header, external_mipmaps.clone(), root_input, input)
     })(input)?;
 
-    Ok((input, BlpImage { header, content }))
+    Ok((input, BlpImageRef { header, content }))
 }
 
 fn parse_content<'a, F>(
@@ -113,7 +149,7 @@ fn parse_content<'a, F>(
     external_mipmaps: F,
     original_input: &'a [u8],
     input: &'a [u8],
-) -> Parser<'a, BlpContent>
+) -> Parser<'a, BlpContentRef<'a>>
 where
     F: FnMut(usize) -> Result<Option<&'a [u8]>, Box<dyn std::error::Error>> + Clone,
 {
@@ -122,7 +158,7 @@ where
             let (input, content) = context("jpeg content", |input| {
                 parse_jpeg_content(blp_header, external_mipmaps.clone(), original_input, input)
             })(input)?;
-            Ok((input, BlpContent::Jpeg(content)))
+            Ok((input, BlpContentRef::Jpeg(content)))
         }
         BlpContentTag::Direct => {
             let (input, content) = context("direct content", |input| {
@@ -0,0 +1,442 @@
+use super::direct::{dxtn_format, parse_dxtn_image, parse_raw1_image, parse_raw3_image};
+use super::error::{Error, LoadError, ParseError, ParseErrorKind};
+use super::header::parse_header;
+use super::{no_mipmaps, parse_blp_with_externals, preload_mipmaps, preloaded_mipmaps};
+use crate::types::*;
+use log::*;
+use nom::{bytes::complete::take, error::context, multi::count, number::complete::le_u32, Err};
+use std::borrow::Cow;
+use std::path::Path;
+
+/// How to react on malformed mipmaps
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Strictness {
+    /// Fail the whole file on the first malformed mipmap
+    #[default]
+    Strict,
+    /// Return all mipmaps that could be read and report [Diagnostic] for
+    /// the rest. Only malformed header or missing original image fail.
+    Lenient,
+}
+
+/// Options for [parse_blp_with_options] and [load_blp_with_options]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ParseOptions {
+    /// Whether malformed mipmaps fail the whole file or are reported
+    pub strictness: Strictness,
+}
+
+/// Issue with mipmap level that was recovered in [Strictness::Lenient] mode
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Diagnostic {
+    /// The level cannot be read, so it and all following levels are dropped
+    Skipped(ParseError),
+    /// Size of the level in the mipmap table is less than the size expected
+    /// from the dimensions. The expected amount of bytes is read instead.
+    WrongSize {
+        /// Mipmap level, 0 is the original image
+        level: usize,
+        /// Size in bytes from the mipmap table
+        declared: usize,
+        /// Size in bytes that the dimensions and format require
+        expected: usize,
+    },
+    /// Data of the level ends before the expected size. Direct content is
+    /// padded with zeros, JPEG content is kept as is.
+    Truncated {
+        /// Mipmap level, 0 is the original image
+        level: usize,
+        /// Size in bytes that should have been read
+        expected: usize,
+        /// Size in bytes that is left in the input
+        actual: usize,
+    },
+}
+
+impl Diagnostic {
+    /// Mipmap level the issue is related to
+    pub fn level(&self) -> usize {
+        match self {
+            Diagnostic::Skipped(e) => e.mipmap.unwrap_or(0),
+            Diagnostic::WrongSize { level, .. } => *level,
+            Diagnostic::Truncated { level, .. } => *level,
+        }
+    }
+}
+
+/// Result of [parse_blp_with_options]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParsedBlp {
+    /// Image with all levels that could be read
+    pub image: BlpImage,
+    /// Issues found in mipmaps, always empty for [Strictness::Strict] mode
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+/// Parse BLP file from slice with given options. External mipmaps of BLP0 are
+/// not available, use [load_blp_with_options] for them.
+pub fn parse_blp_with_options(
+    input: &[u8],
+    options: &ParseOptions,
+) -> Result<ParsedBlp, LoadError> {
+    parse_with_options(input, no_mipmaps, options)
+}
+
+/// Read BLP file from file system with given options. If it BLP0 format, uses
+/// the mipmaps near the root file.
+pub fn load_blp_with_options<Q>(path: Q, options: &ParseOptions) -> Result<ParsedBlp, LoadError>
+where
+    Q: AsRef<Path>,
+{
+    let input =
+        std::fs::read(&path).map_err(|e| LoadError::FileSystem(path.as_ref().to_owned(), e))?;
+    let mipmaps = preload_mipmaps(&path)?;
+    parse_with_options(&input, |i| preloaded_mipmaps(&mipmaps, i), options)
+}
+
+fn parse_with_options<'a, F>(
+    input: &'a [u8],
+    external_mipmaps: F,
+    options: &ParseOptions,
+) -> Result<ParsedBlp, LoadError>
+where
+    F: FnMut(usize) -> Result<Option<&'a [u8]>, Box<dyn std::error::Error>> + Clone,
+{
+    match options.strictness {
+        Strictness::Strict => {
+            let (_, image) = parse_blp_with_externals(input, external_mipmaps)
+                .map_err(|e| LoadError::parsing(e, input, 0))?;
+            Ok(ParsedBlp {
+                image,
+                diagnostics: vec![],
+            })
+        }
+        Strictness::Lenient => parse_lenient(input, external_mipmaps),
+    }
+}
+
+/// How pixels of single level are stored
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum LevelFormat {
+    Jpeg,
+    Raw1,
+    Raw3,
+    Dxtn(DxtnFormat),
+}
+
+impl LevelFormat {
+    pub(crate) fn new(header: &BlpHeader) -> Result<Self, Error<&'static [u8]>> {
+        if header.content == BlpContentTag::Jpeg {
+            return Ok(LevelFormat::Jpeg);
+        }
+        match header.flags {
+            BlpFlags::Old { .. } => Ok(LevelFormat::Raw1),
+            BlpFlags::Blp2 {
+                compression,
+                alpha_bits,
+                alpha_type,
+                ..
+            } => {
+                if header.mipmap_locator == MipmapLocator::External {
+                    return Err(Error::Blp2NoExternalMips);
+                }
+                match compression {
+                    Compression::Jpeg => Err(Error::Blp2UnexpectedJpegCompression),
+                    Compression::Raw1 => Ok(LevelFormat::Raw1),
+                    Compression::Raw3 => Ok(LevelFormat::Raw3),
+                    Compression::Dxtc => dxtn_format(alpha_type, alpha_bits)
+                        .map(LevelFormat::Dxtn)
+                        .ok_or(Error::Blp2UnknownAlphaType(alpha_type)),
+                }
+            }
+        }
+    }
+
+    /// Amount of bytes the level should occupy, unknown for JPEG
+    pub(crate) fn expected_size(&self, header: &BlpHeader, level: usize) -> Option<usize> {
+        let (width, height) = header.mipmap_size(level);
+        let n = width as usize * height as usize;
+        match self {
+            LevelFormat::Jpeg => None,
+            LevelFormat::Raw1 => Some(n + (n * header.alpha_bits() as usize).div_ceil(8)),
+            LevelFormat::Raw3 => Some(n * 4),
+            LevelFormat::Dxtn(format) => {
+                let blocks = (width as usize).div_ceil(4) * (height as usize).div_ceil(4);
+                Some(blocks * format.block_size())
+            }
+        }
+    }
+}
+
+fn parse_lenient<'a, F>(input: &'a [u8], mut external_mipmaps: F) -> Result<ParsedBlp, LoadError>
+where
+    F: FnMut(usize) -> Result<Option<&'a [u8]>, Box<dyn std::error::Error>>,
+{
+    let parsing_error = |e| LoadError::parsing(e, input, 0);
+    let (rest, header) = context("header", parse_header)(input).map_err(parsing_error)?;
+    let mut jpeg_header = vec![];
+    let mut cmap = vec![];
+    match header.content {
+        BlpContentTag::Jpeg => {
+            let (rest, size) = context("jpeg header size", le_u32)(rest).map_err(parsing_error)?;
+            // There is two additional bytes that are not covered by the header size
+            let (_, bytes) =
+                context("jpeg header", take(size as usize + 2))(rest).map_err(parsing_error)?;
+            jpeg_header = bytes.to_vec();
+        }
+        BlpContentTag::Direct => {
+            let (_, colors) =
+                context("color palette", count(le_u32, 256))(rest).map_err(parsing_error)?;
+            cmap = colors;
+        }
+    }
+    let format = LevelFormat::new(&header).map_err(|e| parsing_error(Err::Failure(e)))?;
+
+    let levels = if header.has_mipmaps() {
+        (header.mipmaps_count() + 1).min(16)
+    } else {
+        1
+    };
+    let mut diagnostics = vec![];
+    // Levels are borrowed from the input unless they need padding
+    let mut images: Vec<Cow<[u8]>> = vec![];
+    for level in 0..levels {
+        let expected = format.expected_size(&header, level);
+        let bytes = match header.mipmap_locator {
+            MipmapLocator::Internal { offsets, sizes } => {
+                let offset = offsets[level] as usize;
+                let declared = sizes[level] as usize;
+                if level > 0 && declared == 0 {
+                    // Raw3 files usually end the list of mipmaps with zero sizes
+                    if format != LevelFormat::Raw3 {
+                        let err = level_error(ParseErrorKind::MissingImage(level), level, None);
+                        diagnostics.push(Diagnostic::Skipped(err));
+                    }
+                    break;
+                }
+                if offset >= input.len() {
+                    let kind = ParseErrorKind::OutOfBounds(level);
+                    let err = level_error(kind, level, Some(offset));
+                    if level == 0 {
+                        return Err(LoadError::Parsing(err));
+                    }
+                    warn!("Skipping mipmap {} and further: {}", level, err);
+                    diagnostics.push(Diagnostic::Skipped(err));
+                    break;
+                }
+                let size = match expected {
+                    Some(expected) if expected > declared => {
+                        warn!(
+                            "Size of mipmap {} is {}, but {} is expected",
+                            level, declared, expected
+                        );
+                        diagnostics.push(Diagnostic::WrongSize {
+                            level,
+                            declared,
+                            expected,
+                        });
+                        expected
+                    }
+                    _ => declared,
+                };
+                let bytes = &input[offset..(offset + size).min(input.len())];
+                if bytes.len() < size && expected.is_none() {
+                    diagnostics.push(Diagnostic::Truncated {
+                        level,
+                        expected: size,
+                        actual: bytes.len(),
+                    });
+                }
+                bytes
+            }
+            MipmapLocator::External => match external_mipmaps(level) {
+                Ok(Some(bytes)) => bytes,
+                res => {
+                    let kind = match res {
+                        Err(e) => ParseErrorKind::ExternalMipmap(level, e.to_string()),
+                        _ => ParseErrorKind::MissingImage(level),
+                    };
+                    let err = level_error(kind, level, None);
+                    if level == 0 {
+                        return Err(LoadError::Parsing(err));
+                    }
+                    diagnostics.push(Diagnostic::Skipped(err));
+                    break;
+                }
+            },
+        };
+
+        match expected {
+            Some(expected) if bytes.len() < expected => {
+                warn!(
+                    "Mipmap {} has {} bytes of {}, padding with zeros",
+                    level,
+                    bytes.len(),
+                    expected
+                );
+                diagnostics.push(Diagnostic::Truncated {
+                    level,
+                    expected,
+                    actual: bytes.len(),
+                });
+                let mut padded = Vec::with_capacity(expected);
+                padded.extend_from_slice(bytes);
+                padded.resize(expected, 0);
+                images.push(Cow::Owned(padded));
+            }
+            _ => images.push(Cow::Borrowed(bytes)),
+        }
+    }
+
+    let level_parsing_error = |level: usize, e: Err<Error<&[u8]>>| {
+        LoadError::parsing(e.map(|e| e.in_mipmap(level)), &[], 0)
+    };
+    let content = match format {
+        LevelFormat::Jpeg => BlpContent::Jpeg(BlpJpeg {
+            header: jpeg_header,
+            images: images.into_iter().map(Cow::into_owned).collect(),
+        }),
+        LevelFormat::Raw1 => {
+            let mut parsed = vec![];
+            for (level, bytes) in images.iter().enumerate() {
+                let (_, image) = parse_raw1_image(&header, level, bytes)
+                    .map_err(|e| level_parsing_error(level, e))?;
+                parsed.push(image.to_owned());
+            }
+            BlpContent::Raw1(BlpRaw1 {
+                cmap,
+                images: parsed,
+            })
+        }
+        LevelFormat::Raw3 => {
+            let mut parsed = vec![];
+            for (level, bytes) in images.iter().enumerate() {
+                let (_, image) = parse_raw3_image(&header, level, bytes)
+                    .map_err(|e| level_parsing_error(level, e))?;
+                parsed.push(image.to_owned());
+            }
+            BlpContent::Raw3(BlpRaw3 {
+                cmap,
+                images: parsed,
+            })
+        }
+        LevelFormat::Dxtn(format) => {
+            let mut parsed = vec![];
+            for (level, bytes) in images.iter().enumerate() {
+                let (_, image) = parse_dxtn_image(&header, format, level, bytes)
+                    .map_err(|e| level_parsing_error(level, e))?;
+                parsed.push(image.to_owned());
+            }
+            let content = BlpDxtn {
+                format,
+                cmap,
+                images: parsed,
+            };
+            match format {
+                DxtnFormat::Dxt1 => BlpContent::Dxt1(content),
+                DxtnFormat::Dxt3 => BlpContent::Dxt3(content),
+                DxtnFormat::Dxt5 => BlpContent::Dxt5(content),
+            }
+        }
+    };
+
+    Ok(ParsedBlp {
+        image: BlpImage { header, content },
+        diagnostics,
+    })
+}
+
+fn level_error(kind: ParseErrorKind, level: usize, offset: Option<usize>) -> ParseError {
+    ParseError {
+        kind,
+        context: vec![],
+        offset,
+        mipmap: Some(level),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::convert::{blp_to_image, Blp2Format, BlpTarget};
+    use crate::encode::encode_blp;
+    use crate::parser::parse_blp;
+    use crate::test_util::test_blp;
+    use test_log::test;
+
+    fn test_bytes() -> (BlpImage, Vec<u8>) {
+        let blp = test_blp(BlpTarget::Blp2(Blp2Format::Raw3));
+        let bytes = encode_blp(&blp).expect("encoded");
+        (blp, bytes)
+    }
+
+    fn lenient() -> ParseOptions {
+        ParseOptions {
+            strictness: Strictness::Lenient,
+        }
+    }
+
+    #[test]
+    fn test_lenient_valid() {
+        let (blp, bytes) = test_bytes();
+        let parsed = parse_blp_with_options(&bytes, &lenient()).expect("parsed");
+        assert_eq!(parsed.image, blp);
+        assert!(parsed.diagnostics.is_empty());
+    }
+
+    #[test]
+    fn test_lenient_truncated() {
+        let (blp, mut bytes) = test_bytes();
+        let (offsets, _) = blp.header.internal_mipmaps().expect("internal");
+        bytes.truncate(offsets[2] as usize + 1);
+        assert!(parse_blp_with_options(&bytes, &ParseOptions::default()).is_err());
+
+        let parsed = parse_blp_with_options(&bytes, &lenient()).expect("parsed");
+        assert_eq!(parsed.image.image_count(), 3);
+        for level in 0..2 {
+            assert_eq!(
+                blp_to_image(&parsed.image, level).expect("converted"),
+                blp_to_image(&blp, level).expect("converted")
+            );
+        }
+        assert!(blp_to_image(&parsed.image, 2).is_ok());
+        assert_eq!(
+            parsed.diagnostics[0],
+            Diagnostic::Truncated {
+                level: 2,
+                expected: 8 * 8 * 4,
+                actual: 1
+            }
+        );
+        assert_eq!(parsed.diagnostics[1].level(), 3);
+        assert!(matches!(&parsed.diagnostics[1], Diagnostic::Skipped(e) if e.is_truncated()));
+    }
+
+    #[test]
+    fn test_lenient_wrong_size() {
+        let (blp, mut bytes) = test_bytes();
+        // sizes of mipmaps are located after the offsets in BLP2 header
+        let size_pos = 20 + 16 * 4 + 4;
+        bytes[size_pos..size_pos + 4].copy_from_slice(&1u32.to_le_bytes());
+        assert!(parse_blp(&bytes).is_err());
+
+        let parsed = parse_blp_with_options(&bytes, &lenient()).expect("parsed");
+        assert_eq!(parsed.image.content, blp.content);
+        assert_eq!(
+            parsed.diagnostics,
+            vec![Diagnostic::WrongSize {
+                level: 1,
+                declared: 1,
+                expected: 16 * 16 * 4
+            }]
+        );
+    }
+
+    #[test]
+    fn test_lenient_missing_original() {
+        let (blp, bytes) = test_bytes();
+        let (offsets, _) = blp.header.internal_mipmaps().expect("internal");
+        let res = parse_blp_with_options(&bytes[..offsets[0] as usize], &lenient());
+        assert!(matches!(res, Err(LoadError::Parsing(e)) if e.mipmap == Some(0)));
+    }
+}
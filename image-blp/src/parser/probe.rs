@@ -0,0 +1,136 @@
+use super::error::LoadError;
+use super::header::parse_header;
+use super::reader::read_bytes;
+use crate::types::*;
+use nom::{error::context, number::complete::le_u32};
+use std::io::Read;
+
+/// Summary of BLP file that is read from the header only, without parsing
+/// of color map or mipmaps. Useful for listing of textures.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct BlpInfo {
+    pub version: BlpVersion,
+    pub content: BlpContentTag,
+    /// Compression field of BLP2 header, [None] for BLP0 and BLP1.
+    pub compression: Option<Compression>,
+    pub alpha_bits: u32,
+    /// Alpha type field of BLP2 header, [None] for BLP0 and BLP1.
+    pub alpha_type: Option<u8>,
+    pub width: u32,
+    pub height: u32,
+    /// Count of mipmaps declared by the header. Level 0 (original image)
+    /// is not counted.
+    pub mipmaps_count: usize,
+    /// Offset and size of each internal level, including the original image.
+    /// Empty for external mipmaps of BLP0.
+    pub mipmaps: Vec<(u32, u32)>,
+    /// Size of JPEG header that is shared by all levels, only for JPEG content
+    pub jpeg_header_size: Option<u32>,
+}
+
+impl BlpInfo {
+    fn new(header: &BlpHeader, jpeg_header_size: Option<u32>) -> Self {
+        let (compression, alpha_type) = match header.flags {
+            BlpFlags::Blp2 {
+                compression,
+                alpha_type,
+                ..
+            } => (Some(compression), Some(alpha_type)),
+            BlpFlags::Old { .. } => (None, None),
+        };
+        let mipmaps = header
+            .internal_mipmaps()
+            .map(|(offsets, sizes)| {
+                offsets
+                    .into_iter()
+                    .zip(sizes)
+                    .take((header.mipmaps_count() + 1).min(16))
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        BlpInfo {
+            version: header.version,
+            content: header.content,
+            compression,
+            alpha_bits: header.alpha_bits(),
+            alpha_type,
+            width: header.width,
+            height: header.height,
+            mipmaps_count: header.mipmaps_count(),
+            mipmaps,
+            jpeg_header_size,
+        }
+    }
+}
+
+/// Read only header of BLP file (and size of JPEG header) from the slice
+pub fn probe_blp(root_input: &[u8]) -> Result<BlpInfo, LoadError> {
+    let parsing_error = |e| LoadError::parsing(e, root_input, 0);
+    let (input, header) = context("header", parse_header)(root_input).map_err(parsing_error)?;
+    let jpeg_header_size = if header.content == BlpContentTag::Jpeg {
+        let (_, size) = context("jpeg header size", le_u32)(input).map_err(parsing_error)?;
+        Some(size)
+    } else {
+        None
+    };
+    Ok(BlpInfo::new(&header, jpeg_header_size))
+}
+
+/// Read only header of BLP file (and size of JPEG header) from the stream.
+/// Only first bytes that could hold the header are consumed from the stream.
+pub fn probe_blp_from_reader<R: Read>(mut reader: R) -> Result<BlpInfo, LoadError> {
+    let input = read_bytes(&mut reader, BlpHeader::size(BlpVersion::Blp1) + 4)?;
+    probe_blp(&input)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::convert::{Blp2Format, BlpOldFormat, BlpTarget};
+    use crate::encode::encode_blp;
+    use crate::test_util::{image_blp, test_image};
+    use test_log::test;
+
+    #[test]
+    fn test_probe() {
+        let targets = [
+            BlpTarget::Blp1(BlpOldFormat::Jpeg {
+                has_alpha: false,
+                options: Default::default(),
+            }),
+            BlpTarget::Blp2(Blp2Format::Raw3),
+        ];
+        for target in targets {
+            let blp = image_blp(test_image(64, 32, 255), true, target);
+            let bytes = encode_blp(&blp).expect("encoded");
+
+            let info = probe_blp(&bytes).expect("probed");
+            assert_eq!(info.version, blp.header.version);
+            assert_eq!((info.width, info.height), (64, 32));
+            assert_eq!(info.mipmaps_count, 6);
+            assert_eq!(info.mipmaps.len(), 7);
+            let (offsets, sizes) = blp.header.internal_mipmaps().expect("internal");
+            assert_eq!(info.mipmaps[3], (offsets[3], sizes[3]));
+            match &blp.content {
+                BlpContent::Jpeg(jpeg) => {
+                    assert_eq!(info.compression, None);
+                    assert_eq!(info.jpeg_header_size, Some(jpeg.header.len() as u32 - 2));
+                }
+                _ => {
+                    assert_eq!(info.compression, Some(Compression::Raw3));
+                    assert_eq!(info.jpeg_header_size, None);
+                }
+            }
+
+            let header_only = &bytes[..BlpHeader::size(blp.header.version) + 4];
+            let from_reader = probe_blp_from_reader(header_only).expect("probed");
+            assert_eq!(from_reader, info);
+        }
+    }
+
+    #[test]
+    fn test_probe_truncated() {
+        assert!(probe_blp(b"BLP1\0\0\0\0").is_err());
+    }
+}
@@ -0,0 +1,323 @@
+use super::direct::{dxtn_format, parse_dxtn_image, parse_raw1_image, parse_raw3_image};
+use super::error::{Error, LoadError};
+use super::header::parse_header;
+use crate::types::*;
+use log::*;
+use nom::{multi::count, number::complete::le_u32, Err};
+use std::io::{Read, Seek, SeekFrom};
+
+/// Lazy reader of BLP files. Parses only header, mipmap locator and
+/// color map on creation, JPEG header and pixel data of mipmaps are
+/// read only on demand with [BlpReader::read_mipmap].
+///
+/// That is useful when only a single level is needed from large amount of
+/// files, e.g. small mipmap for thumbnails. External mipmaps of BLP0 are
+/// not supported, use [load_blp](super::load_blp) for them.
+///
+/// ```no_run
+/// # use image_blp::{convert::blp_to_image, parser::BlpReader};
+/// let file = std::fs::File::open("test.blp").expect("opened");
+/// let mut reader = BlpReader::new(std::io::BufReader::new(file)).expect("header");
+/// let level = reader.mipmaps_count();
+/// let mipmap = reader.read_mipmap(level).expect("mipmap");
+/// let thumbnail = blp_to_image(&mipmap, level).expect("converted");
+/// ```
+pub struct BlpReader<R> {
+    reader: R,
+    start: u64,
+    length: u64,
+    header: BlpHeader,
+    /// Offset from the start and size of JPEG header including padding
+    jpeg_header_location: (u64, u64),
+    jpeg_header: Option<Vec<u8>>,
+    cmap: Vec<u32>,
+}
+
+impl<R: Read + Seek> BlpReader<R> {
+    /// Read header of BLP file from the stream. The stream is expected
+    /// to be positioned at the start of the file.
+    pub fn new(mut reader: R) -> Result<Self, LoadError> {
+        let start = reader.stream_position().map_err(LoadError::Stream)?;
+        let end = reader.seek(SeekFrom::End(0)).map_err(LoadError::Stream)?;
+        let length = end.saturating_sub(start);
+        reader
+            .seek(SeekFrom::Start(start))
+            .map_err(LoadError::Stream)?;
+
+        let header_bytes = read_bytes(&mut reader, BlpHeader::size(BlpVersion::Blp1))?;
+        let (rest, header) =
+            parse_header(&header_bytes).map_err(|e| LoadError::parsing(e, &header_bytes, 0))?;
+        let header_size = (header_bytes.len() - rest.len()) as u64;
+        reader
+            .seek(SeekFrom::Start(start + header_size))
+            .map_err(LoadError::Stream)?;
+
+        let mut jpeg_header_location = (0, 0);
+        let mut cmap = vec![];
+        match header.content {
+            BlpContentTag::Jpeg => {
+                let size_bytes = read_bytes(&mut reader, 4)?;
+                let (_, jpeg_header_size) = le_u32(size_bytes.as_slice())
+                    .map_err(|e| LoadError::parsing(e, &size_bytes, header_size as usize))?;
+                // There is two additional bytes that are not covered by the header size
+                jpeg_header_location = (header_size + 4, jpeg_header_size as u64 + 2);
+            }
+            BlpContentTag::Direct => {
+                let bytes = read_bytes(&mut reader, 256 * 4)?;
+                let (_, colors) = count(le_u32, 256)(bytes.as_slice())
+                    .map_err(|e| LoadError::parsing(e, &bytes, header_size as usize))?;
+                cmap = colors;
+            }
+        }
+
+        Ok(BlpReader {
+            reader,
+            start,
+            length,
+            header,
+            jpeg_header_location,
+            jpeg_header: None,
+            cmap,
+        })
+    }
+
+    /// Get parsed header of the BLP file
+    pub fn header(&self) -> &BlpHeader {
+        &self.header
+    }
+
+    /// Get count of mipmaps that the header declares. Level 0 (original
+    /// image) is not counted.
+    pub fn mipmaps_count(&self) -> usize {
+        self.header.mipmaps_count().min(15)
+    }
+
+    /// Read JPEG header shared by all mipmaps once. Failures are reported for
+    /// the mipmap `level` that needs the header.
+    fn load_jpeg_header(&mut self, level: usize) -> Result<(), LoadError> {
+        if self.jpeg_header.is_some() {
+            return Ok(());
+        }
+        let (offset, size) = self.jpeg_header_location;
+        if offset + size > self.length {
+            error!(
+                "JPEG header needed for mipmap {} is out of bounds! {} > {}",
+                level,
+                offset + size,
+                self.length
+            );
+            return Err(Err::Failure(Error::<&[u8]>::OutOfBounds(level)).into());
+        }
+        self.reader
+            .seek(SeekFrom::Start(self.start + offset))
+            .map_err(LoadError::Stream)?;
+        self.jpeg_header = Some(read_bytes(&mut self.reader, size as usize)?);
+        Ok(())
+    }
+
+    /// Read given mipmap level from the stream. The result could be passed to
+    /// [blp_to_image](crate::convert::blp_to_image) with the same level.
+    pub fn read_mipmap(&mut self, level: usize) -> Result<BlpMipmap<'_>, LoadError> {
+        if level > self.mipmaps_count() {
+            return Err(Err::Failure(Error::<&[u8]>::MissingImage(level)).into());
+        }
+        let (offsets, sizes) = self
+            .header
+            .internal_mipmaps()
+            .ok_or(Err::Failure(Error::<&[u8]>::MissingImage(level)))?;
+        if self.header.content == BlpContentTag::Jpeg {
+            self.load_jpeg_header(level)?;
+        }
+        let offset = offsets[level] as u64;
+        let size = sizes[level] as u64;
+        if offset >= self.length {
+            error!(
+                "Offset of mipmap {} is out of bounds! {} >= {}",
+                level, offset, self.length
+            );
+            return Err(Err::Failure(Error::<&[u8]>::OutOfBounds(level)).into());
+        }
+        if offset + size > self.length {
+            error!(
+                "Offset+size of mipmap {} is out of bounds! {} > {}",
+                level,
+                offset + size,
+                self.length
+            );
+            return Err(Err::Failure(Error::<&[u8]>::OutOfBounds(level)).into());
+        }
+        self.reader
+            .seek(SeekFrom::Start(self.start + offset))
+            .map_err(LoadError::Stream)?;
+        let bytes = read_bytes(&mut self.reader, size as usize)?;
+        let mipmap_error = |e: Err<Error<&[u8]>>| {
+            LoadError::parsing(e.map(|e| e.in_mipmap(level)), &bytes, offset as usize)
+        };
+
+        let content = match self.header.flags {
+            _ if self.header.content == BlpContentTag::Jpeg => BlpMipmapContent::Jpeg {
+                header: self.jpeg_header.as_deref().unwrap_or_default(),
+                image: bytes,
+            },
+            BlpFlags::Old { .. } => {
+                let (_, image) =
+                    parse_raw1_image(&self.header, level, &bytes).map_err(mipmap_error)?;
+                BlpMipmapContent::Raw1 {
+                    cmap: &self.cmap,
+                    image: image.to_owned(),
+                }
+            }
+            BlpFlags::Blp2 {
+                compression,
+                alpha_bits,
+                alpha_type,
+                ..
+            } => match compression {
+                Compression::Jpeg => {
+                    return Err(Err::Failure(Error::<&[u8]>::Blp2UnexpectedJpegCompression).into())
+                }
+                Compression::Raw1 => {
+                    let (_, image) =
+                        parse_raw1_image(&self.header, level, &bytes).map_err(mipmap_error)?;
+                    BlpMipmapContent::Raw1 {
+                        cmap: &self.cmap,
+                        image: image.to_owned(),
+                    }
+                }
+                Compression::Raw3 => {
+                    if level > 0 && size == 0 {
+                        return Err(Err::Failure(Error::<&[u8]>::MissingImage(level)).into());
+                    }
+                    let (_, image) =
+                        parse_raw3_image(&self.header, level, &bytes).map_err(mipmap_error)?;
+                    BlpMipmapContent::Raw3(image.to_owned())
+                }
+                Compression::Dxtc => {
+                    let format = dxtn_format(alpha_type, alpha_bits).ok_or(Err::Failure(
+                        Error::<&[u8]>::Blp2UnknownAlphaType(alpha_type),
+                    ))?;
+                    let (_, image) = parse_dxtn_image(&self.header, format, level, &bytes)
+                        .map_err(mipmap_error)?;
+                    BlpMipmapContent::Dxtn {
+                        format,
+                        image: image.to_owned(),
+                    }
+                }
+            },
+        };
+
+        Ok(BlpMipmap {
+            header: &self.header,
+            level,
+            content,
+        })
+    }
+
+    /// Return the underlying stream
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+}
+
+/// Single mipmap level read by [BlpReader::read_mipmap]. Color map and JPEG
+/// header shared by all levels are borrowed from the reader.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlpMipmap<'a> {
+    pub header: &'a BlpHeader,
+    /// Mipmap level that is read, 0 is the original image
+    pub level: usize,
+    pub content: BlpMipmapContent<'a>,
+}
+
+/// Pixel data of [BlpMipmap]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BlpMipmapContent<'a> {
+    Jpeg {
+        /// JPEG header shared by all levels, including 2 padding bytes
+        header: &'a [u8],
+        image: Vec<u8>,
+    },
+    Raw1 {
+        cmap: &'a [u32],
+        image: Raw1Image,
+    },
+    Raw3(Raw3Image),
+    Dxtn {
+        format: DxtnFormat,
+        image: DxtnImage,
+    },
+}
+
+/// Read exactly given amount of bytes or until the end of stream
+pub(super) fn read_bytes<R: Read>(reader: &mut R, amount: usize) -> Result<Vec<u8>, LoadError> {
+    let mut buf = vec![];
+    reader
+        .take(amount as u64)
+        .read_to_end(&mut buf)
+        .map_err(LoadError::Stream)?;
+    Ok(buf)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::convert::{blp_to_image, Blp2Format, BlpTarget, Error as ConvertError};
+    use crate::encode::encode_blp;
+    use crate::test_util::test_blp;
+    use std::io::Cursor;
+    use test_log::test;
+
+    #[test]
+    fn test_read_each_mipmap() {
+        for target in [
+            BlpTarget::Blp1(Default::default()),
+            BlpTarget::Blp2(Blp2Format::Raw3),
+            BlpTarget::Blp2(Default::default()),
+        ] {
+            let blp = test_blp(target);
+            let bytes = encode_blp(&blp).expect("encoded");
+            let mut reader = BlpReader::new(Cursor::new(&bytes)).expect("header");
+            assert_eq!(reader.header(), &blp.header);
+            assert_eq!(reader.mipmaps_count() + 1, blp.image_count());
+            for level in (0..blp.image_count()).rev() {
+                let mipmap = reader.read_mipmap(level).expect("mipmap");
+                assert_eq!(mipmap.level, level);
+                assert_eq!(
+                    blp_to_image(&mipmap, level).expect("converted"),
+                    blp_to_image(&blp, level).expect("converted")
+                );
+                assert!(matches!(
+                    blp_to_image(&mipmap, level + 1),
+                    Err(ConvertError::MissingImage(l)) if l == level + 1
+                ));
+            }
+            assert!(reader.read_mipmap(blp.image_count()).is_err());
+        }
+    }
+
+    #[test]
+    fn test_read_out_of_bounds() {
+        let blp = test_blp(BlpTarget::Blp2(Blp2Format::Raw3));
+        let mut bytes = encode_blp(&blp).expect("encoded");
+        let (offsets, _) = blp.header.internal_mipmaps().expect("internal");
+        bytes.truncate(offsets[2] as usize + 1);
+        let mut reader = BlpReader::new(Cursor::new(&bytes)).expect("header");
+        assert!(reader.read_mipmap(0).is_ok());
+        assert!(reader.read_mipmap(1).is_ok());
+        assert!(reader.read_mipmap(2).is_err());
+        assert!(reader.read_mipmap(3).is_err());
+    }
+
+    #[test]
+    fn test_read_jpeg_header_out_of_bounds() {
+        let blp = test_blp(BlpTarget::Blp1(Default::default()));
+        let mut bytes = encode_blp(&blp).expect("encoded");
+        // Declare JPEG header larger than the whole file
+        let size_offset = BlpHeader::size(BlpVersion::Blp1);
+        bytes[size_offset..size_offset + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+        let mut reader = BlpReader::new(Cursor::new(&bytes)).expect("header");
+        let err = reader.read_mipmap(2).expect_err("out of bounds");
+        let err = err.parse_error().expect("parse error");
+        assert_eq!(err.mipmap, Some(2));
+    }
+}
@@ -1,8 +1,11 @@
-use image::DynamicImage;
+use ::image::DynamicImage;
 
-use crate::convert::blp_to_image;
+use crate::convert::{blp_to_image, Blp2Format, BlpOldFormat, BlpTarget};
+use crate::encode::encode_blp;
+use crate::test_util::test_blp;
+use crate::types::*;
 
-use super::load_blp_from_buf;
+use super::{load_blp_from_buf, parse_blp, parse_blp_ref};
 
 pub mod blp0;
 pub mod blp1;
@@ -18,3 +21,44 @@ fn test_from_buf() {
     let image: DynamicImage = blp_to_image(&blp_file, 0).expect("converted");
     image.save(output_filename).expect("saved");
 }
+
+#[test]
+fn test_parse_ref() {
+    let targets = [
+        BlpTarget::Blp1(BlpOldFormat::Jpeg {
+            has_alpha: true,
+            options: Default::default(),
+        }),
+        BlpTarget::Blp2(Blp2Format::Raw3),
+        BlpTarget::Blp2(Default::default()),
+    ];
+    for target in targets {
+        let blp = test_blp(target);
+        let bytes = encode_blp(&blp).expect("encoded");
+        let (_, parsed) = parse_blp(&bytes).expect("parsed");
+        let (_, parsed_ref) = parse_blp_ref(&bytes).expect("parsed");
+        assert_eq!(parsed_ref.to_owned(), parsed);
+
+        let input = bytes.as_ptr_range();
+        let borrowed = |slice: &[u8]| {
+            let range = slice.as_ptr_range();
+            input.start <= range.start && range.end <= input.end
+        };
+        let mipmaps: Vec<&[u8]> = match &parsed_ref.content {
+            BlpContentRef::Jpeg(v) => v.images.clone(),
+            BlpContentRef::Raw1(v) => v.images.iter().map(|i| i.indexed_rgb).collect(),
+            BlpContentRef::Raw3(v) => v.images.iter().map(|i| i.pixels).collect(),
+            BlpContentRef::Dxt1(v) | BlpContentRef::Dxt3(v) | BlpContentRef::Dxt5(v) => {
+                v.images.iter().map(|i| i.content).collect()
+            }
+        };
+        assert!(mipmaps.iter().all(|m| borrowed(m)));
+
+        for level in 0..parsed_ref.image_count() {
+            assert_eq!(
+                blp_to_image(&parsed_ref, level).expect("converted"),
+                blp_to_image(&parsed, level).expect("converted")
+            );
+        }
+    }
+}
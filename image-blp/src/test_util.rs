@@ -0,0 +1,26 @@
+use crate::convert::{image_to_blp, BlpTarget, FilterType};
+use crate::types::BlpImage;
+use ::image::{DynamicImage, Rgba, RgbaImage};
+
+/// Gradient image with the same alpha for all pixels
+pub fn test_image(width: u32, height: u32, alpha: u8) -> RgbaImage {
+    RgbaImage::from_fn(width, height, |x, y| {
+        Rgba([(x * 8) as u8, (y * 8) as u8, 64, alpha])
+    })
+}
+
+/// Convert the image with the nearest filter for mipmaps
+pub fn image_blp(image: RgbaImage, make_mipmaps: bool, target: BlpTarget) -> BlpImage {
+    image_to_blp(
+        DynamicImage::ImageRgba8(image),
+        make_mipmaps,
+        target,
+        FilterType::Nearest,
+    )
+    .expect("converted")
+}
+
+/// Opaque 32x32 gradient with all mipmaps
+pub fn test_blp(target: BlpTarget) -> BlpImage {
+    image_blp(test_image(32, 32, 255), true, target)
+}
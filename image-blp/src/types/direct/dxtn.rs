@@ -54,7 +54,7 @@ impl BlpDxtn {
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[derive(Debug, Clone, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct DxtnImage {
     pub content: Vec<u8>,
 }
@@ -69,3 +69,37 @@ impl DxtnImage {
         self.content.is_empty()
     }
 }
+
+/// Borrowed version of [BlpDxtn]. Color map is always copied as it is tiny.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct BlpDxtnRef<'a> {
+    pub format: DxtnFormat,
+    pub cmap: Vec<u32>,
+    pub images: Vec<DxtnImageRef<'a>>,
+}
+
+impl<'a> BlpDxtnRef<'a> {
+    /// Copy all mipmaps into owned [BlpDxtn]
+    pub fn to_owned(&self) -> BlpDxtn {
+        BlpDxtn {
+            format: self.format,
+            cmap: self.cmap.clone(),
+            images: self.images.iter().map(|i| i.to_owned()).collect(),
+        }
+    }
+}
+
+/// Borrowed version of [DxtnImage] that points into the parsed buffer
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct DxtnImageRef<'a> {
+    pub content: &'a [u8],
+}
+
+impl<'a> DxtnImageRef<'a> {
+    /// Copy the mipmap into owned [DxtnImage]
+    pub fn to_owned(&self) -> DxtnImage {
+        DxtnImage {
+            content: self.content.to_vec(),
+        }
+    }
+}
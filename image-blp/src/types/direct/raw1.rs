@@ -32,7 +32,7 @@ impl BlpRaw1 {
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[derive(Debug, Clone, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct Raw1Image {
     /// BGR component values can be obtained by using indexedRGB values as an
     /// index in lutBGR. When producing such values using color matching be
@@ -70,3 +70,37 @@ impl Raw1Image {
         self.indexed_rgb.is_empty() && self.indexed_alpha.is_empty()
     }
 }
+
+/// Borrowed version of [BlpRaw1]. Color map is always copied as it is tiny.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct BlpRaw1Ref<'a> {
+    pub cmap: Vec<u32>,
+    pub images: Vec<Raw1ImageRef<'a>>,
+}
+
+impl<'a> BlpRaw1Ref<'a> {
+    /// Copy all mipmaps into owned [BlpRaw1]
+    pub fn to_owned(&self) -> BlpRaw1 {
+        BlpRaw1 {
+            cmap: self.cmap.clone(),
+            images: self.images.iter().map(|i| i.to_owned()).collect(),
+        }
+    }
+}
+
+/// Borrowed version of [Raw1Image] that points into the parsed buffer
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Raw1ImageRef<'a> {
+    pub indexed_rgb: &'a [u8],
+    pub indexed_alpha: &'a [u8],
+}
+
+impl<'a> Raw1ImageRef<'a> {
+    /// Copy the mipmap into owned [Raw1Image]
+    pub fn to_owned(&self) -> Raw1Image {
+        Raw1Image {
+            indexed_rgb: self.indexed_rgb.to_vec(),
+            indexed_alpha: self.indexed_alpha.to_vec(),
+        }
+    }
+}
@@ -37,7 +37,7 @@ impl BlpRaw3 {
 /// rather than depth, as all images of this type seem to have 4 bytes per
 /// pixel regardless of depth, and it has been seen to exceed 8. Their
 /// meaning is unknown.
-#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[derive(Debug, Clone, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct Raw3Image {
     pub pixels: Vec<u32>,
 }
@@ -52,3 +52,43 @@ impl Raw3Image {
         self.pixels.is_empty()
     }
 }
+
+/// Borrowed version of [BlpRaw3]. Color map is always copied as it is tiny.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct BlpRaw3Ref<'a> {
+    pub cmap: Vec<u32>,
+    pub images: Vec<Raw3ImageRef<'a>>,
+}
+
+impl<'a> BlpRaw3Ref<'a> {
+    /// Copy all mipmaps into owned [BlpRaw3]
+    pub fn to_owned(&self) -> BlpRaw3 {
+        BlpRaw3 {
+            cmap: self.cmap.clone(),
+            images: self.images.iter().map(|i| i.to_owned()).collect(),
+        }
+    }
+}
+
+/// Borrowed version of [Raw3Image]. Pixels are kept as little endian
+/// bytes as the buffer is not guaranteed to be aligned.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Raw3ImageRef<'a> {
+    pub pixels: &'a [u8],
+}
+
+impl<'a> Raw3ImageRef<'a> {
+    /// Iterate over BGRA pixels of the mipmap
+    pub fn pixels(&self) -> impl ExactSizeIterator<Item = u32> + 'a {
+        self.pixels
+            .chunks_exact(4)
+            .map(|c| u32::from_le_bytes([c[0], c[1], c[2], c[3]]))
+    }
+
+    /// Copy the mipmap into owned [Raw3Image]
+    pub fn to_owned(&self) -> Raw3Image {
+        Raw3Image {
+            pixels: self.pixels().collect(),
+        }
+    }
+}
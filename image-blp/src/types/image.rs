@@ -133,3 +133,65 @@ impl BlpContent {
         }
     }
 }
+
+/// Borrowed version of [BlpImage] where mipmaps point into the parsed
+/// buffer. Use [BlpImageRef::to_owned] to get rid of the lifetime.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct BlpImageRef<'a> {
+    pub header: BlpHeader,
+    pub content: BlpContentRef<'a>,
+}
+
+impl<'a> BlpImageRef<'a> {
+    /// Get total amount of images encoded in the content
+    pub fn image_count(&self) -> usize {
+        match &self.content {
+            BlpContentRef::Dxt1(v) => v.images.len(),
+            BlpContentRef::Dxt3(v) => v.images.len(),
+            BlpContentRef::Dxt5(v) => v.images.len(),
+            BlpContentRef::Raw1(v) => v.images.len(),
+            BlpContentRef::Raw3(v) => v.images.len(),
+            BlpContentRef::Jpeg(v) => v.images.len(),
+        }
+    }
+
+    /// Copy all mipmaps into owned [BlpImage]
+    pub fn to_owned(&self) -> BlpImage {
+        BlpImage {
+            header: self.header.clone(),
+            content: self.content.to_owned(),
+        }
+    }
+}
+
+/// Borrowed version of [BlpContent]
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum BlpContentRef<'a> {
+    Jpeg(BlpJpegRef<'a>),
+    Raw1(BlpRaw1Ref<'a>),
+    Raw3(BlpRaw3Ref<'a>),
+    Dxt1(BlpDxtnRef<'a>),
+    Dxt3(BlpDxtnRef<'a>),
+    Dxt5(BlpDxtnRef<'a>),
+}
+
+impl<'a> BlpContentRef<'a> {
+    pub fn tag(&self) -> BlpContentTag {
+        match self {
+            BlpContentRef::Jpeg { .. } => BlpContentTag::Jpeg,
+            _ => BlpContentTag::Direct,
+        }
+    }
+
+    /// Copy all mipmaps into owned [BlpContent]
+    pub fn to_owned(&self) -> BlpContent {
+        match self {
+            BlpContentRef::Jpeg(v) => BlpContent::Jpeg(v.to_owned()),
+            BlpContentRef::Raw1(v) => BlpContent::Raw1(v.to_owned()),
+            BlpContentRef::Raw3(v) => BlpContent::Raw3(v.to_owned()),
+            BlpContentRef::Dxt1(v) => BlpContent::Dxt1(v.to_owned()),
+            BlpContentRef::Dxt3(v) => BlpContent::Dxt3(v.to_owned()),
+            BlpContentRef::Dxt5(v) => BlpContent::Dxt5(v.to_owned()),
+        }
+    }
+}
@@ -57,3 +57,33 @@ impl BlpJpeg {
         MipmapLocator::Internal { offsets, sizes }
     }
 }
+
+/// Borrowed version of [BlpJpeg] that points into the parsed buffer
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct BlpJpegRef<'a> {
+    /// JPEG header that is appended to each mipmap level data
+    pub header: &'a [u8],
+    /// Image itself and all mipmaps levels.
+    pub images: Vec<&'a [u8]>,
+}
+
+impl<'a> BlpJpegRef<'a> {
+    /// Concat JPEG header with body and get the required mipmap level.
+    pub fn full_jpeg(&self, i: usize) -> Option<Vec<u8>> {
+        let body = self.images.get(i)?;
+        // Remove those bugged 2 bytes from the end
+        let header_size = self.header.len() - 2;
+        let mut buffer = Vec::with_capacity(header_size + body.len());
+        buffer.extend(&self.header[0..header_size]);
+        buffer.extend(*body);
+        Some(buffer)
+    }
+
+    /// Copy header and all mipmaps into owned [BlpJpeg]
+    pub fn to_owned(&self) -> BlpJpeg {
+        BlpJpeg {
+            header: self.header.to_vec(),
+            images: self.images.iter().map(|i| i.to_vec()).collect(),
+        }
+    }
+}
@@ -0,0 +1,531 @@
+use crate::parser::{parse_blp, parse_header, LevelFormat, LoadError};
+use crate::types::jpeg::MAX_JPEG_HEADER;
+use crate::types::*;
+use std::fmt;
+
+/// How serious is the deviation from the format
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Severity {
+    /// The file is valid, but could be improved
+    Info,
+    /// The file deviates from the format, but readers usually cope with it
+    Warning,
+    /// The file cannot be read correctly
+    Error,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Severity::Info => write!(f, "info"),
+            Severity::Warning => write!(f, "warning"),
+            Severity::Error => write!(f, "error"),
+        }
+    }
+}
+
+/// Kind of finding. Each kind has stable code (see [FindingCode::code]) and
+/// fixed severity, so they can be used to filter reports.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum FindingCode {
+    /// `BLP001`: the file cannot be parsed
+    ParseFailed,
+    /// `BLP002`: `alpha_bits` is not one of values allowed for the content
+    NonStandardAlphaBits,
+    /// `BLP003`: `alpha_type` of DXTn content is not known
+    UnknownAlphaType,
+    /// `BLP004`: width or height is not a power of two
+    NonPowerOfTwo,
+    /// `BLP005`: JPEG header is larger than [MAX_JPEG_HEADER]
+    JpegHeaderTooLarge,
+    /// `BLP006`: JPEG header exceeds the file
+    JpegHeaderOutOfBounds,
+    /// `BLP007`: there is no room for 256 colors of color map
+    ColorMapLength,
+    /// `BLP008`: amount of mipmaps differs from [BlpHeader::mipmaps_count]
+    MipmapCountMismatch,
+    /// `BLP009`: mipmap exceeds the file
+    MipmapOutOfBounds,
+    /// `BLP010`: mipmap is smaller than required by [BlpHeader::mipmap_size]
+    MipmapTooSmall,
+    /// `BLP011`: mipmap is larger than required by [BlpHeader::mipmap_size]
+    MipmapTooLarge,
+    /// `BLP012`: offsets of mipmaps are not ascending
+    MipmapsUnordered,
+    /// `BLP013`: mipmap overlaps with other mipmap or header
+    MipmapsOverlap,
+    /// `BLP014`: there are bytes after the last mipmap
+    TrailingBytes,
+}
+
+impl FindingCode {
+    /// Stable identifier of the finding
+    pub fn code(&self) -> &'static str {
+        match self {
+            FindingCode::ParseFailed => "BLP001",
+            FindingCode::NonStandardAlphaBits => "BLP002",
+            FindingCode::UnknownAlphaType => "BLP003",
+            FindingCode::NonPowerOfTwo => "BLP004",
+            FindingCode::JpegHeaderTooLarge => "BLP005",
+            FindingCode::JpegHeaderOutOfBounds => "BLP006",
+            FindingCode::ColorMapLength => "BLP007",
+            FindingCode::MipmapCountMismatch => "BLP008",
+            FindingCode::MipmapOutOfBounds => "BLP009",
+            FindingCode::MipmapTooSmall => "BLP010",
+            FindingCode::MipmapTooLarge => "BLP011",
+            FindingCode::MipmapsUnordered => "BLP012",
+            FindingCode::MipmapsOverlap => "BLP013",
+            FindingCode::TrailingBytes => "BLP014",
+        }
+    }
+
+    pub fn severity(&self) -> Severity {
+        match self {
+            FindingCode::ParseFailed
+            | FindingCode::UnknownAlphaType
+            | FindingCode::JpegHeaderOutOfBounds
+            | FindingCode::ColorMapLength
+            | FindingCode::MipmapOutOfBounds
+            | FindingCode::MipmapTooSmall
+            | FindingCode::MipmapsOverlap => Severity::Error,
+            FindingCode::NonStandardAlphaBits
+            | FindingCode::NonPowerOfTwo
+            | FindingCode::JpegHeaderTooLarge
+            | FindingCode::MipmapCountMismatch
+            | FindingCode::MipmapTooLarge
+            | FindingCode::MipmapsUnordered => Severity::Warning,
+            FindingCode::TrailingBytes => Severity::Info,
+        }
+    }
+}
+
+impl fmt::Display for FindingCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.code())
+    }
+}
+
+/// Single deviation from the format found by [validate]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Finding {
+    pub code: FindingCode,
+    pub severity: Severity,
+    /// Mipmap level the finding is related to
+    pub mipmap: Option<usize>,
+    /// Byte offset in the file the finding is related to
+    pub offset: Option<usize>,
+    /// Human readable description
+    pub message: String,
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}: {}", self.code, self.severity, self.message)
+    }
+}
+
+/// Result of [validate]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ValidationReport {
+    pub findings: Vec<Finding>,
+}
+
+impl ValidationReport {
+    /// No findings with [Severity::Error]
+    pub fn is_valid(&self) -> bool {
+        self.max_severity() < Some(Severity::Error)
+    }
+
+    /// The most serious severity among the findings
+    pub fn max_severity(&self) -> Option<Severity> {
+        self.findings.iter().map(|f| f.severity).max()
+    }
+
+    /// Check whether there is a finding with the given code
+    pub fn has(&self, code: FindingCode) -> bool {
+        self.findings.iter().any(|f| f.code == code)
+    }
+
+    fn push(
+        &mut self,
+        code: FindingCode,
+        mipmap: Option<usize>,
+        offset: Option<usize>,
+        message: String,
+    ) {
+        self.findings.push(Finding {
+            code,
+            severity: code.severity(),
+            mipmap,
+            offset,
+            message,
+        });
+    }
+}
+
+impl fmt::Display for ValidationReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for finding in self.findings.iter() {
+            writeln!(f, "{}", finding)?;
+        }
+        Ok(())
+    }
+}
+
+/// Check BLP file for all deviations from the format that we know about.
+/// Unlike the parser, it doesn't stop on the first problem.
+pub fn validate(input: &[u8]) -> ValidationReport {
+    let mut report = ValidationReport::default();
+    let header = match parse_header(input) {
+        Ok((_, header)) => header,
+        Err(e) => {
+            let msg = LoadError::parsing(e, input, 0).to_string();
+            report.push(FindingCode::ParseFailed, None, Some(0), msg);
+            return report;
+        }
+    };
+    let header_size = BlpHeader::size(header.version);
+
+    check_alpha_bits(&mut report, &header, input);
+    for (name, value) in [("Width", header.width), ("Height", header.height)] {
+        if !value.is_power_of_two() {
+            let msg = format!("{} {} is not a power of two", name, value);
+            report.push(FindingCode::NonPowerOfTwo, None, None, msg);
+        }
+    }
+
+    let format = match LevelFormat::new(&header) {
+        Ok(format) => Some(format),
+        Err(e) => {
+            let code = match e {
+                crate::parser::Error::Blp2UnknownAlphaType(_) => FindingCode::UnknownAlphaType,
+                _ => FindingCode::ParseFailed,
+            };
+            report.push(code, None, None, e.to_string());
+            None
+        }
+    };
+
+    // Region right after the header that is occupied by JPEG header or color map
+    let data_start = match header.content {
+        BlpContentTag::Jpeg => {
+            let size_bytes = input.get(header_size..header_size + 4);
+            let jpeg_size = size_bytes.map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]));
+            match jpeg_size {
+                Some(size) => {
+                    if size as usize > MAX_JPEG_HEADER {
+                        let msg = format!(
+                            "JPEG header size {} is greater than {}",
+                            size, MAX_JPEG_HEADER
+                        );
+                        report.push(
+                            FindingCode::JpegHeaderTooLarge,
+                            None,
+                            Some(header_size),
+                            msg,
+                        );
+                    }
+                    // There is two additional bytes that are not covered by the header size
+                    let end = header_size + 4 + size as usize + 2;
+                    if end > input.len() {
+                        let msg = format!(
+                            "JPEG header ends at {}, but file size is {}",
+                            end,
+                            input.len()
+                        );
+                        report.push(
+                            FindingCode::JpegHeaderOutOfBounds,
+                            None,
+                            Some(header_size),
+                            msg,
+                        );
+                    }
+                    end
+                }
+                None => {
+                    let msg = "File ends before JPEG header size".to_owned();
+                    report.push(
+                        FindingCode::JpegHeaderOutOfBounds,
+                        None,
+                        Some(header_size),
+                        msg,
+                    );
+                    header_size + 4
+                }
+            }
+        }
+        BlpContentTag::Direct => header_size + 256 * 4,
+    };
+
+    if let Some((offsets, sizes)) = header.internal_mipmaps() {
+        check_mipmaps(
+            &mut report,
+            &header,
+            format,
+            input,
+            data_start,
+            &offsets,
+            &sizes,
+        );
+    } else if header.content == BlpContentTag::Direct && data_start > input.len() {
+        let msg = format!(
+            "Color map needs {} bytes, but there are only {}",
+            256 * 4,
+            input.len().saturating_sub(header_size)
+        );
+        report.push(FindingCode::ColorMapLength, None, Some(header_size), msg);
+    }
+
+    // Catch everything else that the parser rejects
+    if report.is_valid() && header.internal_mipmaps().is_some() {
+        if let Err(e) = parse_blp(input) {
+            let msg = LoadError::parsing(e, input, 0).to_string();
+            report.push(FindingCode::ParseFailed, None, None, msg);
+        }
+    }
+
+    report
+}
+
+fn check_alpha_bits(report: &mut ValidationReport, header: &BlpHeader, input: &[u8]) {
+    // The parser rewrites non standard values and BlpHeader::alpha_bits
+    // reports fixed value for RAW3, so read the raw field
+    let (raw, offset) = match header.flags {
+        BlpFlags::Old { .. } => {
+            let raw = u32::from_le_bytes([input[8], input[9], input[10], input[11]]);
+            (raw, 8)
+        }
+        BlpFlags::Blp2 { .. } => (input[9] as u32, 9),
+    };
+    let allowed: &[u32] = match header.flags {
+        _ if header.content == BlpContentTag::Jpeg => &[0, 8],
+        BlpFlags::Blp2 {
+            compression: Compression::Raw3,
+            ..
+        } => &[0, 8],
+        _ => &[0, 1, 4, 8],
+    };
+    if !allowed.contains(&raw) {
+        let msg = format!("Alpha bits {} is not one of {:?}", raw, allowed);
+        report.push(FindingCode::NonStandardAlphaBits, None, Some(offset), msg);
+    }
+}
+
+fn check_mipmaps(
+    report: &mut ValidationReport,
+    header: &BlpHeader,
+    format: Option<LevelFormat>,
+    input: &[u8],
+    data_start: usize,
+    offsets: &[u32; 16],
+    sizes: &[u32; 16],
+) {
+    let header_size = BlpHeader::size(header.version);
+    let present: Vec<usize> = (0..16)
+        .filter(|&i| offsets[i] != 0 && sizes[i] != 0)
+        .collect();
+    let expected_count = if header.has_mipmaps() {
+        (header.mipmaps_count() + 1).min(16)
+    } else {
+        1
+    };
+    if present.len() != expected_count {
+        let msg = format!(
+            "There are {} images, but {} are expected for {}x{}",
+            present.len(),
+            expected_count,
+            header.width,
+            header.height
+        );
+        report.push(FindingCode::MipmapCountMismatch, None, None, msg);
+    }
+
+    if header.content == BlpContentTag::Direct {
+        let cmap_end = present
+            .iter()
+            .map(|&i| offsets[i] as usize)
+            .min()
+            .unwrap_or(input.len())
+            .min(input.len());
+        if cmap_end < data_start {
+            let msg = format!(
+                "Color map needs {} bytes, but there are only {}",
+                256 * 4,
+                cmap_end.saturating_sub(header_size)
+            );
+            report.push(FindingCode::ColorMapLength, None, Some(header_size), msg);
+        }
+    }
+
+    let mut prev_offset = 0;
+    let mut ranges: Vec<(usize, usize, usize)> = vec![];
+    for &i in present.iter() {
+        let offset = offsets[i] as usize;
+        let size = sizes[i] as usize;
+        let end = offset + size;
+        if offset < prev_offset {
+            let msg = format!("Mipmap {} is located before the previous one", i);
+            report.push(FindingCode::MipmapsUnordered, Some(i), Some(offset), msg);
+        }
+        prev_offset = offset;
+
+        if end > input.len() {
+            let msg = format!(
+                "Mipmap {} ends at {}, but file size is {}",
+                i,
+                end,
+                input.len()
+            );
+            report.push(FindingCode::MipmapOutOfBounds, Some(i), Some(offset), msg);
+        }
+        if header.content == BlpContentTag::Direct && offset < data_start {
+            let msg = format!("Mipmap {} overlaps with color map", i);
+            report.push(FindingCode::MipmapsOverlap, Some(i), Some(offset), msg);
+        } else if offset < data_start {
+            let msg = format!("Mipmap {} overlaps with JPEG header", i);
+            report.push(FindingCode::MipmapsOverlap, Some(i), Some(offset), msg);
+        }
+        if let Some(&(j, _, _)) = ranges.iter().find(|(_, s, e)| offset < *e && *s < end) {
+            let msg = format!("Mipmap {} overlaps with mipmap {}", i, j);
+            report.push(FindingCode::MipmapsOverlap, Some(i), Some(offset), msg);
+        }
+        ranges.push((i, offset, end));
+
+        if let Some(expected) = format.and_then(|f| f.expected_size(header, i)) {
+            let (width, height) = header.mipmap_size(i);
+            if size < expected {
+                let msg = format!(
+                    "Mipmap {} ({}x{}) has {} bytes, but {} are expected",
+                    i, width, height, size, expected
+                );
+                report.push(FindingCode::MipmapTooSmall, Some(i), Some(offset), msg);
+            } else if size > expected {
+                let msg = format!(
+                    "Mipmap {} ({}x{}) has {} bytes, but only {} are needed",
+                    i, width, height, size, expected
+                );
+                report.push(FindingCode::MipmapTooLarge, Some(i), Some(offset), msg);
+            }
+        }
+    }
+
+    let used_end = ranges
+        .iter()
+        .map(|(_, _, e)| *e)
+        .max()
+        .unwrap_or(0)
+        .max(data_start);
+    if used_end < input.len() {
+        let msg = format!(
+            "There are {} unused bytes at the end of file",
+            input.len() - used_end
+        );
+        report.push(FindingCode::TrailingBytes, None, Some(used_end), msg);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::convert::{Blp2Format, BlpOldFormat, BlpTarget};
+    use crate::encode::encode_blp;
+    use crate::test_util::test_blp;
+    use test_log::test;
+
+    fn test_bytes(target: BlpTarget) -> Vec<u8> {
+        encode_blp(&test_blp(target)).expect("encoded")
+    }
+
+    // BLP2 header: magic, content, flags, width, height, offsets, sizes
+    const OFFSETS: usize = 20;
+    const SIZES: usize = OFFSETS + 16 * 4;
+
+    fn set_u32(bytes: &mut [u8], pos: usize, value: u32) {
+        bytes[pos..pos + 4].copy_from_slice(&value.to_le_bytes());
+    }
+
+    fn get_u32(bytes: &[u8], pos: usize) -> u32 {
+        u32::from_le_bytes([bytes[pos], bytes[pos + 1], bytes[pos + 2], bytes[pos + 3]])
+    }
+
+    #[test]
+    fn test_valid_files() {
+        let targets = [
+            BlpTarget::Blp1(BlpOldFormat::Jpeg {
+                has_alpha: true,
+                options: Default::default(),
+            }),
+            BlpTarget::Blp2(Blp2Format::Raw3),
+            BlpTarget::Blp2(Default::default()),
+        ];
+        for target in targets {
+            let report = validate(&test_bytes(target));
+            assert!(report.findings.is_empty(), "{}", report);
+        }
+    }
+
+    #[test]
+    fn test_not_blp() {
+        let report = validate(b"GIF89a");
+        assert!(!report.is_valid());
+        assert_eq!(report.findings[0].code.code(), "BLP001");
+    }
+
+    #[test]
+    fn test_alpha_bits_and_dimensions() {
+        let mut bytes = test_bytes(BlpTarget::Blp1(BlpOldFormat::Jpeg {
+            has_alpha: true,
+            options: Default::default(),
+        }));
+        set_u32(&mut bytes, 8, 3);
+        set_u32(&mut bytes, 12, 33);
+        let report = validate(&bytes);
+        assert!(report.has(FindingCode::NonStandardAlphaBits));
+        assert!(report.has(FindingCode::NonPowerOfTwo));
+        assert_eq!(report.max_severity(), Some(Severity::Warning));
+
+        // BLP2 flags: compression, alpha_bits, alpha_type, has_mipmaps
+        let mut bytes = test_bytes(BlpTarget::Blp2(Blp2Format::Raw3));
+        bytes[9] = 3;
+        let report = validate(&bytes);
+        let finding = report
+            .findings
+            .iter()
+            .find(|f| f.code == FindingCode::NonStandardAlphaBits)
+            .expect("alpha bits finding");
+        assert_eq!(finding.offset, Some(9));
+    }
+
+    #[test]
+    fn test_mipmap_table() {
+        let mut bytes = test_bytes(BlpTarget::Blp2(Blp2Format::Raw3));
+        // Point mipmap 2 into the mipmap 0 and grow the mipmap 3
+        let offset0 = get_u32(&bytes, OFFSETS);
+        set_u32(&mut bytes, OFFSETS + 2 * 4, offset0 + 4);
+        let size3 = get_u32(&bytes, SIZES + 3 * 4);
+        set_u32(&mut bytes, SIZES + 3 * 4, size3 + 4);
+        bytes.extend([0; 10]);
+
+        let report = validate(&bytes);
+        assert!(report.has(FindingCode::MipmapsOverlap));
+        assert!(report.has(FindingCode::MipmapTooLarge));
+        assert!(report.has(FindingCode::MipmapsUnordered));
+        assert!(report.has(FindingCode::TrailingBytes));
+        assert!(!report.is_valid());
+    }
+
+    #[test]
+    fn test_truncated() {
+        let mut bytes = test_bytes(BlpTarget::Blp2(Blp2Format::Raw3));
+        let offset1 = get_u32(&bytes, OFFSETS + 4);
+        bytes.truncate(offset1 as usize + 4);
+        let report = validate(&bytes);
+        let finding = report
+            .findings
+            .iter()
+            .find(|f| f.code == FindingCode::MipmapOutOfBounds)
+            .expect("out of bounds");
+        assert_eq!(finding.mipmap, Some(1));
+        assert_eq!(finding.severity, Severity::Error);
+    }
+}